@@ -1,9 +1,75 @@
+use nih_plug::prelude::{Enum, util};
+use crate::filters::Crossover;
+
+#[derive(Enum, PartialEq, Eq, Debug, Copy, Clone)]
+pub enum KeySource {
+    #[name = "Key:Self"]
+    Internal,
+    #[name = "Key:Self Mid"]
+    InternalMid,
+    #[name = "Key:Self Side"]
+    InternalSide,
+    #[name = "Key:External"]
+    External,
+    #[name = "Key:Ext Mid"]
+    ExternalMid,
+    #[name = "Key:Ext Side"]
+    ExternalSide,
+}
+
+#[derive(Enum, PartialEq, Eq, Debug, Copy, Clone)]
+pub enum DetectionMode {
+    #[name = "Detect:Peak"]
+    Peak,
+    #[name = "Detect:RMS"]
+    Rms,
+}
+
+impl KeySource {
+    // Pick the left and right signals the ducking listens to
+    pub fn key(&self, in_l: f32, in_r: f32, sc_l: f32, sc_r: f32) -> (f32, f32) {
+        match self {
+            KeySource::Internal => (in_l, in_r),
+            KeySource::InternalMid => {
+                let mid = (in_l + in_r) * 0.5;
+                (mid, mid)
+            },
+            KeySource::InternalSide => {
+                let side = (in_l - in_r) * 0.5;
+                (side, side)
+            },
+            KeySource::External => (sc_l, sc_r),
+            KeySource::ExternalMid => {
+                let mid = (sc_l + sc_r) * 0.5;
+                (mid, mid)
+            },
+            KeySource::ExternalSide => {
+                let side = (sc_l - sc_r) * 0.5;
+                (side, side)
+            },
+        }
+    }
+}
+
+// Fixed smoothing on the gain reduction to round off the corners of the envelope
+const SMOOTHING_MS: f32 = 2.0;
+// Anything quieter than this is treated as silence by the level detection
+const MIN_LEVEL_DB: f32 = -120.0;
+// Averaging time for RMS detection
+const RMS_WINDOW_MS: f32 = 10.0;
+
 pub struct Ducking {
     trigger_threshold: f32,
+    ratio: f32,
+    range: f32,
+    knee: f32,
     attack_time: f32,
     release_time: f32,
+    hold_time: f32,
     attack_coeff: f32,
     release_coeff: f32,
+    hold_samples: u32,
+    hold_counter: u32,
     gain_reduction: f32,
     envelope: f32,
     sample_rate: f32,
@@ -11,66 +77,376 @@ pub struct Ducking {
     smoothed_gain_reduction: f32,
 }
 
+// One pole coefficient that gets about 63% of the way to its target in the given time
+pub(crate) fn time_to_coeff(time_ms: f32, sample_rate: f32) -> f32 {
+    if time_ms <= 0.0 {
+        0.0
+    } else {
+        (-1.0 / (time_ms * 0.001 * sample_rate)).exp()
+    }
+}
+
 impl Ducking {
-    // Not really sure how to get around the sample rate here on creation
-    pub fn new(trigger_threshold: f32, attack_time: f32, release_time: f32, sample_rate_new: f32) -> Self {
+    // Times are all in milliseconds, threshold range and knee in dB
+    pub fn new(trigger_threshold: f32, attack_time: f32, release_time: f32, hold_time: f32, sample_rate_new: f32) -> Self {
         Ducking {
             trigger_threshold,
+            ratio: 4.0,
+            range: 12.0,
+            knee: 6.0,
             attack_time,
             release_time,
+            hold_time,
             gain_reduction: 1.0,
             envelope: 0.0,
-            attack_coeff: (-1.0 / (attack_time * sample_rate_new)).exp(),
-            release_coeff: (-1.0 / (release_time * sample_rate_new)).exp(),
+            attack_coeff: time_to_coeff(attack_time, sample_rate_new),
+            release_coeff: time_to_coeff(release_time, sample_rate_new),
+            hold_samples: (hold_time * 0.001 * sample_rate_new) as u32,
+            hold_counter: 0,
             sample_rate: sample_rate_new,
-            smoothing_coeff: 0.9999, // Adjust this value to control the smoothing amount
+            smoothing_coeff: time_to_coeff(SMOOTHING_MS, sample_rate_new),
             smoothed_gain_reduction: 1.0,
         }
     }
 
-    // I couldn't get these to work so removed them for now
-    #[allow(dead_code)]
     pub fn update_attack(&mut self, new_attack: f32, sample_rate_new: f32) {
-        self.sample_rate = sample_rate_new;
         self.attack_time = new_attack;
-        self.attack_coeff = (-1.0 / (self.attack_time*1000.0 * self.sample_rate)).exp();
+        self.update_sample_rate(sample_rate_new);
     }
 
-    // I couldn't get these to work so removed them for now
-    #[allow(dead_code)]
     pub fn update_release(&mut self, new_release: f32, sample_rate_new: f32) {
-        self.sample_rate = sample_rate_new;
         self.release_time = new_release;
-        self.release_coeff = (-1.0 / (self.release_time*1000.0 * self.sample_rate)).exp();
+        self.update_sample_rate(sample_rate_new);
+    }
+
+    pub fn update_hold(&mut self, new_hold: f32, sample_rate_new: f32) {
+        self.hold_time = new_hold;
+        self.update_sample_rate(sample_rate_new);
     }
 
     pub fn update_threshold(&mut self, new_threshold: f32, sample_rate_new: f32) {
-        self.sample_rate = sample_rate_new;
         self.trigger_threshold = new_threshold;
+        self.update_sample_rate(sample_rate_new);
     }
 
-    pub fn process(&mut self, input: f32, trigger: f32) -> f32 {
-        // Calculate the envelope based on the trigger signal - threshold is in db already
-        let target_envelope = if 20.0*trigger.log10() > self.trigger_threshold {
-            1.0
-        } else {
+    // Shape of the gain computer: ratio, maximum depth and knee width
+    pub fn update_curve(&mut self, new_ratio: f32, new_range: f32, new_knee: f32) {
+        self.ratio = new_ratio.max(1.0);
+        self.range = new_range.max(0.0);
+        self.knee = new_knee.max(0.0);
+    }
+
+    // Recalculate everything that depends on the sample rate
+    fn update_sample_rate(&mut self, sample_rate_new: f32) {
+        self.sample_rate = sample_rate_new;
+        self.attack_coeff = time_to_coeff(self.attack_time, self.sample_rate);
+        self.release_coeff = time_to_coeff(self.release_time, self.sample_rate);
+        self.hold_samples = (self.hold_time * 0.001 * self.sample_rate) as u32;
+        self.smoothing_coeff = time_to_coeff(SMOOTHING_MS, self.sample_rate);
+    }
+
+    // Forget any envelope state, used when the host resets the plugin
+    pub fn reset(&mut self) {
+        self.envelope = 0.0;
+        self.hold_counter = 0;
+        self.gain_reduction = 1.0;
+        self.smoothed_gain_reduction = 1.0;
+    }
+
+    // How many dB to duck for a key at this level, a soft knee compressor curve limited by the range
+    fn compute_reduction(&self, level_db: f32) -> f32 {
+        let over = level_db - self.trigger_threshold;
+        let slope = 1.0 / self.ratio - 1.0;
+        let reduction = if 2.0 * over < -self.knee {
             0.0
+        } else if self.knee > 0.0 && 2.0 * over.abs() <= self.knee {
+            slope * (over + self.knee * 0.5).powi(2) / (2.0 * self.knee)
+        } else {
+            slope * over
         };
+        reduction.max(-self.range)
+    }
+
+    // Takes the key level in dB and returns the gain to apply
+    pub fn process(&mut self, level_db: f32) -> f32 {
+        let target_reduction = self.compute_reduction(level_db);
 
-        // Update the envelope
-        self.envelope = if target_envelope > self.envelope {
-            self.envelope * (1.0 - self.attack_coeff) + target_envelope * self.attack_coeff
+        // The envelope is the current reduction in dB, attack moves it down and release brings it back
+        if target_reduction < self.envelope {
+            self.hold_counter = self.hold_samples;
+            self.envelope = target_reduction + self.attack_coeff * (self.envelope - target_reduction);
+        } else if self.hold_counter > 0 {
+            // Hold keeps us ducked for a bit after the trigger drops
+            self.hold_counter -= 1;
         } else {
-            self.envelope * (1.0 - self.release_coeff) + target_envelope * self.release_coeff
-        };
+            self.envelope = target_reduction + self.release_coeff * (self.envelope - target_reduction);
+        }
 
         // Calculate gain reduction based on the envelope
-        self.gain_reduction = 1.0 - self.envelope;
+        self.gain_reduction = util::db_to_gain(self.envelope);
 
         // Smooth out the gain reduction using a low-pass filter
-        self.smoothed_gain_reduction = self.smoothed_gain_reduction * self.smoothing_coeff + self.gain_reduction * (1.0 - self.smoothing_coeff);
+        self.smoothed_gain_reduction = self.gain_reduction + self.smoothing_coeff * (self.smoothed_gain_reduction - self.gain_reduction);
+
+        self.smoothed_gain_reduction
+    }
+}
+
+// One ducking stage for the whole wet signal with linkable left and right detection
+pub struct StereoDucking {
+    ducking_l: Ducking,
+    ducking_r: Ducking,
+    detection: DetectionMode,
+    link: f32,
+    rms_coeff: f32,
+    mean_square_l: f32,
+    mean_square_r: f32,
+}
+
+impl StereoDucking {
+    pub fn new(trigger_threshold: f32, attack_time: f32, release_time: f32, hold_time: f32, sample_rate_new: f32) -> Self {
+        StereoDucking {
+            ducking_l: Ducking::new(trigger_threshold, attack_time, release_time, hold_time, sample_rate_new),
+            ducking_r: Ducking::new(trigger_threshold, attack_time, release_time, hold_time, sample_rate_new),
+            detection: DetectionMode::Peak,
+            link: 1.0,
+            rms_coeff: time_to_coeff(RMS_WINDOW_MS, sample_rate_new),
+            mean_square_l: 0.0,
+            mean_square_r: 0.0,
+        }
+    }
+
+    pub fn update_attack(&mut self, new_attack: f32, sample_rate_new: f32) {
+        self.ducking_l.update_attack(new_attack, sample_rate_new);
+        self.ducking_r.update_attack(new_attack, sample_rate_new);
+    }
+
+    pub fn update_release(&mut self, new_release: f32, sample_rate_new: f32) {
+        self.ducking_l.update_release(new_release, sample_rate_new);
+        self.ducking_r.update_release(new_release, sample_rate_new);
+    }
+
+    pub fn update_hold(&mut self, new_hold: f32, sample_rate_new: f32) {
+        self.ducking_l.update_hold(new_hold, sample_rate_new);
+        self.ducking_r.update_hold(new_hold, sample_rate_new);
+    }
+
+    pub fn update_threshold(&mut self, new_threshold: f32, sample_rate_new: f32) {
+        self.ducking_l.update_threshold(new_threshold, sample_rate_new);
+        self.ducking_r.update_threshold(new_threshold, sample_rate_new);
+    }
+
+    pub fn update_curve(&mut self, new_ratio: f32, new_range: f32, new_knee: f32) {
+        self.ducking_l.update_curve(new_ratio, new_range, new_knee);
+        self.ducking_r.update_curve(new_ratio, new_range, new_knee);
+    }
+
+    // Peak or RMS detection and how much each side follows the louder one
+    pub fn update_detection(&mut self, new_detection: DetectionMode, new_link: f32) {
+        self.detection = new_detection;
+        self.link = new_link.clamp(0.0, 1.0);
+    }
+
+    pub fn reset(&mut self) {
+        self.ducking_l.reset();
+        self.ducking_r.reset();
+        self.mean_square_l = 0.0;
+        self.mean_square_r = 0.0;
+    }
+
+    // Returns the left and right gains for this sample, call once per sample
+    pub fn process(&mut self, key_l: f32, key_r: f32) -> (f32, f32) {
+        let (level_l, level_r) = match self.detection {
+            DetectionMode::Peak => (key_l.abs(), key_r.abs()),
+            DetectionMode::Rms => {
+                self.mean_square_l = key_l * key_l + self.rms_coeff * (self.mean_square_l - key_l * key_l);
+                self.mean_square_r = key_r * key_r + self.rms_coeff * (self.mean_square_r - key_r * key_r);
+                (self.mean_square_l.sqrt(), self.mean_square_r.sqrt())
+            },
+        };
+
+        // Work in dB from the absolute level so negative or silent keys don't turn into NaN
+        let level_l_db = util::gain_to_db(level_l).max(MIN_LEVEL_DB);
+        let level_r_db = util::gain_to_db(level_r).max(MIN_LEVEL_DB);
 
-        // Apply smoothed gain reduction to the input signal
-        input * self.smoothed_gain_reduction
+        // Fully linked both sides react to whichever is louder so the image stays put
+        let linked_db = level_l_db.max(level_r_db);
+        (
+            self.ducking_l.process(level_l_db + self.link * (linked_db - level_l_db)),
+            self.ducking_r.process(level_r_db + self.link * (linked_db - level_r_db)),
+        )
     }
-}
\ No newline at end of file
+}
+
+// Most bands the wet can be split into, slots are low, mid and high
+pub const MAX_DUCK_BANDS: usize = 3;
+
+// Split points used until the params come in
+const DEFAULT_LOW_CROSSOVER: f32 = 250.0;
+const DEFAULT_HIGH_CROSSOVER: f32 = 4000.0;
+
+// Splits the wet and the key the same way so each band only ducks from its own part of the key
+pub struct MultibandDucking {
+    bands: [StereoDucking; MAX_DUCK_BANDS],
+    band_count: usize,
+    wet_low_split: Crossover,
+    wet_high_split: Crossover,
+    low_allpass: Crossover,
+    key_low_split: Crossover,
+    key_high_split: Crossover,
+}
+
+impl MultibandDucking {
+    pub fn new(trigger_threshold: f32, attack_time: f32, release_time: f32, hold_time: f32, sample_rate_new: f32) -> Self {
+        MultibandDucking {
+            bands: std::array::from_fn(|_| StereoDucking::new(trigger_threshold, attack_time, release_time, hold_time, sample_rate_new)),
+            band_count: 1,
+            wet_low_split: Crossover::new(DEFAULT_LOW_CROSSOVER, sample_rate_new),
+            wet_high_split: Crossover::new(DEFAULT_HIGH_CROSSOVER, sample_rate_new),
+            low_allpass: Crossover::new(DEFAULT_HIGH_CROSSOVER, sample_rate_new),
+            key_low_split: Crossover::new(DEFAULT_LOW_CROSSOVER, sample_rate_new),
+            key_high_split: Crossover::new(DEFAULT_HIGH_CROSSOVER, sample_rate_new),
+        }
+    }
+
+    // Which band slots run, two bands skip the mid
+    fn active_bands(&self) -> &'static [usize] {
+        match self.band_count {
+            1 => &[0],
+            2 => &[0, 2],
+            _ => &[0, 1, 2],
+        }
+    }
+
+    pub fn update_attack(&mut self, new_attack: f32, sample_rate_new: f32) {
+        self.bands.iter_mut().for_each(|band| band.update_attack(new_attack, sample_rate_new));
+    }
+
+    pub fn update_release(&mut self, new_release: f32, sample_rate_new: f32) {
+        self.bands.iter_mut().for_each(|band| band.update_release(new_release, sample_rate_new));
+    }
+
+    pub fn update_hold(&mut self, new_hold: f32, sample_rate_new: f32) {
+        self.bands.iter_mut().for_each(|band| band.update_hold(new_hold, sample_rate_new));
+    }
+
+    pub fn update_detection(&mut self, new_detection: DetectionMode, new_link: f32) {
+        self.bands.iter_mut().for_each(|band| band.update_detection(new_detection, new_link));
+    }
+
+    pub fn update_band_threshold(&mut self, band: usize, new_threshold: f32, sample_rate_new: f32) {
+        self.bands[band].update_threshold(new_threshold, sample_rate_new);
+    }
+
+    pub fn update_band_curve(&mut self, band: usize, new_ratio: f32, new_range: f32, new_knee: f32) {
+        self.bands[band].update_curve(new_ratio, new_range, new_knee);
+    }
+
+    // Split frequencies in Hz, two bands only use the low one
+    pub fn update_crossovers(&mut self, low_hz: f32, high_hz: f32, sample_rate_new: f32) {
+        let high_hz = high_hz.max(low_hz);
+        self.wet_low_split.update_params(low_hz, sample_rate_new);
+        self.key_low_split.update_params(low_hz, sample_rate_new);
+        self.wet_high_split.update_params(high_hz, sample_rate_new);
+        self.key_high_split.update_params(high_hz, sample_rate_new);
+        self.low_allpass.update_params(high_hz, sample_rate_new);
+    }
+
+    // Crossovers that were sitting idle get cleared so they don't replay old audio
+    pub fn set_band_count(&mut self, new_count: usize) {
+        let new_count = new_count.clamp(1, MAX_DUCK_BANDS);
+        if new_count != self.band_count {
+            self.band_count = new_count;
+            self.reset_crossovers();
+        }
+    }
+
+    fn reset_crossovers(&mut self) {
+        self.wet_low_split.reset();
+        self.wet_high_split.reset();
+        self.low_allpass.reset();
+        self.key_low_split.reset();
+        self.key_high_split.reset();
+    }
+
+    pub fn reset(&mut self) {
+        self.bands.iter_mut().for_each(StereoDucking::reset);
+        self.reset_crossovers();
+    }
+
+    // Returns the ducked wet and the average gain across the bands, call once per sample
+    pub fn process(&mut self, wet_l: f32, wet_r: f32, key_l: f32, key_r: f32) -> ((f32, f32), f32) {
+        if self.band_count == 1 {
+            let (gain_l, gain_r) = self.bands[0].process(key_l, key_r);
+            return ((wet_l * gain_l, wet_r * gain_r), (gain_l + gain_r) * 0.5);
+        }
+
+        let (wet_low, wet_rest) = self.wet_low_split.split(wet_l, wet_r);
+        let (key_low, key_rest) = self.key_low_split.split(key_l, key_r);
+        let mut band_wet = [wet_low, (0.0, 0.0), wet_rest];
+        let mut band_key = [key_low, (0.0, 0.0), key_rest];
+        if self.band_count == 3 {
+            (band_wet[1], band_wet[2]) = self.wet_high_split.split(wet_rest.0, wet_rest.1);
+            (band_key[1], band_key[2]) = self.key_high_split.split(key_rest.0, key_rest.1);
+            // The low band never went through the high split so give it the same phase
+            band_wet[0] = self.low_allpass.allpass(wet_low.0, wet_low.1);
+        }
+
+        let mut ducked = (0.0, 0.0);
+        let mut gain_sum = 0.0;
+        for &band in self.active_bands() {
+            let (gain_l, gain_r) = self.bands[band].process(band_key[band].0, band_key[band].1);
+            ducked.0 += band_wet[band].0 * gain_l;
+            ducked.1 += band_wet[band].1 * gain_r;
+            gain_sum += gain_l + gain_r;
+        }
+        (ducked, gain_sum / (2 * self.band_count) as f32)
+    }
+}
+
+// Delays the audio so the ducking can react before a transient arrives
+pub struct Lookahead {
+    buffers: Vec<Vec<f32>>,
+    write_index: usize,
+    delay: usize,
+}
+
+impl Lookahead {
+    // Allocates every channel up front, delays are in samples
+    pub fn new(channels: usize, max_delay: usize) -> Self {
+        Lookahead {
+            buffers: vec![vec![0.0; max_delay + 1]; channels],
+            write_index: 0,
+            delay: 0,
+        }
+    }
+
+    pub fn set_delay(&mut self, new_delay: usize) {
+        let max_delay = self.buffers.first().map_or(0, |buffer| buffer.len() - 1);
+        self.delay = new_delay.min(max_delay);
+    }
+
+    pub fn delay(&self) -> usize {
+        self.delay
+    }
+
+    pub fn reset(&mut self) {
+        self.buffers.iter_mut().flatten().for_each(|sample| *sample = 0.0);
+        self.write_index = 0;
+    }
+
+    // Write this channel's sample and get back the delayed one, call advance() once every channel is done
+    pub fn process(&mut self, channel: usize, input: f32) -> f32 {
+        let Some(buffer) = self.buffers.get_mut(channel) else {
+            return input;
+        };
+        let buffer_len = buffer.len();
+        buffer[self.write_index] = input;
+        buffer[(self.write_index + buffer_len - self.delay) % buffer_len]
+    }
+
+    pub fn advance(&mut self) {
+        let buffer_len = self.buffers.first().map_or(1, |buffer| buffer.len());
+        self.write_index = (self.write_index + 1) % buffer_len;
+    }
+}
@@ -0,0 +1,69 @@
+// Input envelope follower that pushes decay, mix and cutoff around, a gentler take on ducking
+// by Ardura
+
+use nih_plug::prelude::{util, Enum};
+use crate::ducking::time_to_coeff;
+
+// Input level that reads as silence, the envelope runs from here up to 0 dB
+const ENVELOPE_FLOOR_DB: f32 = -60.0;
+
+#[derive(Enum, PartialEq, Eq, Debug, Copy, Clone)]
+pub enum EnvelopeDirection {
+    #[name = "Env:Louder More"]
+    LouderMore,
+    #[name = "Env:Louder Less"]
+    LouderLess,
+}
+
+pub(crate) struct EnvelopeFollower {
+    level: f32,
+    attack_time: f32,
+    release_time: f32,
+    attack_coeff: f32,
+    release_coeff: f32,
+}
+
+impl EnvelopeFollower {
+    pub(crate) fn new(attack_time: f32, release_time: f32, sample_rate: f32) -> Self {
+        EnvelopeFollower {
+            level: 0.0,
+            attack_time,
+            release_time,
+            attack_coeff: time_to_coeff(attack_time, sample_rate),
+            release_coeff: time_to_coeff(release_time, sample_rate),
+        }
+    }
+
+    // Cheap to call every sample, only recalculates when the times actually move
+    pub(crate) fn update_times(&mut self, attack_time: f32, release_time: f32, sample_rate: f32) {
+        if attack_time != self.attack_time {
+            self.attack_time = attack_time;
+            self.attack_coeff = time_to_coeff(attack_time, sample_rate);
+        }
+        if release_time != self.release_time {
+            self.release_time = release_time;
+            self.release_coeff = time_to_coeff(release_time, sample_rate);
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.level = 0.0;
+    }
+
+    // Peak follower on the louder side, returned as 0 at the floor up to 1 at 0 dB
+    pub(crate) fn process(&mut self, input_l: f32, input_r: f32) -> f32 {
+        let peak = input_l.abs().max(input_r.abs());
+        let coeff = if peak > self.level { self.attack_coeff } else { self.release_coeff };
+        self.level = peak + coeff * (self.level - peak);
+        ((util::gain_to_db(self.level) - ENVELOPE_FLOOR_DB) / -ENVELOPE_FLOOR_DB).clamp(0.0, 1.0)
+    }
+}
+
+// Negative amounts scale the value down towards zero, positive ones push it up towards max
+pub(crate) fn modulate(value: f32, max: f32, amount: f32) -> f32 {
+    if amount < 0.0 {
+        value * (1.0 + amount)
+    } else {
+        value + (max - value) * amount
+    }
+}
@@ -1,54 +1,480 @@
-// Filters for rust plugins inspired by Airwindows iir filter format
+// Filters for rust plugins, RBJ cookbook biquads and a Linkwitz-Riley crossover
 // by Ardura
 
+use std::f32;
+use nih_plug::prelude::Enum;
+
+/// Q that gives a flat Butterworth response for the low and high pass
+pub(crate) const BUTTERWORTH_Q: f32 = f32::consts::FRAC_1_SQRT_2;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum FilterType {
+	LowPass,
+	HighPass,
+	FirstOrderLowPass,
+	FirstOrderHighPass,
+	LowShelf,
+	HighShelf,
+	Peak,
+}
+
+/// RBJ cookbook biquad running the same curve on left and right, frequency is in Hz
 #[derive(Clone, Copy)]
-pub(crate) struct StereoFilter {
-    cutoff_frequency: f32,
-    l_old: f32,
-	r_old: f32,
-	l_old2: f32,
-	r_old2: f32,
+pub(crate) struct StereoBiquad {
+	filter_type: FilterType,
+	b0: f32,
+	b1: f32,
+	b2: f32,
+	a1: f32,
+	a2: f32,
+	// Transposed direct form II state
+	l_state: [f32; 2],
+	r_state: [f32; 2],
+}
+
+impl StereoBiquad {
+	pub(crate) fn new(filter_type: FilterType, frequency: f32, q: f32, gain_db: f32, sample_rate: f32) -> StereoBiquad {
+		let mut biquad = StereoBiquad {
+			filter_type,
+			b0: 1.0,
+			b1: 0.0,
+			b2: 0.0,
+			a1: 0.0,
+			a2: 0.0,
+			l_state: [0.0; 2],
+			r_state: [0.0; 2],
+		};
+		biquad.update_params(frequency, q, gain_db, sample_rate);
+		biquad
+	}
+
+	/// Recalculate the coefficients, gain only matters for the shelves and peak
+	pub(crate) fn update_params(&mut self, frequency: f32, q: f32, gain_db: f32, sample_rate: f32) {
+		// Keep the frequency under nyquist and Q above zero so nothing blows up
+		let frequency = frequency.clamp(1.0, sample_rate * 0.49);
+		let q = q.max(0.01);
+		let w0 = f32::consts::TAU * frequency / sample_rate;
+		let (sin_w0, cos_w0) = w0.sin_cos();
+		let alpha = sin_w0 / (2.0 * q);
+		let a = 10.0_f32.powf(gain_db / 40.0);
+
+		// Bilinear one pole for the 6 dB slopes
+		let k = (w0 * 0.5).tan();
+
+		let (b0, b1, b2, a0, a1, a2) = match self.filter_type {
+			FilterType::LowPass => (
+				(1.0 - cos_w0) * 0.5, 1.0 - cos_w0, (1.0 - cos_w0) * 0.5,
+				1.0 + alpha, -2.0 * cos_w0, 1.0 - alpha,
+			),
+			FilterType::HighPass => (
+				(1.0 + cos_w0) * 0.5, -(1.0 + cos_w0), (1.0 + cos_w0) * 0.5,
+				1.0 + alpha, -2.0 * cos_w0, 1.0 - alpha,
+			),
+			FilterType::FirstOrderLowPass => (
+				k, k, 0.0,
+				k + 1.0, k - 1.0, 0.0,
+			),
+			FilterType::FirstOrderHighPass => (
+				1.0, -1.0, 0.0,
+				k + 1.0, k - 1.0, 0.0,
+			),
+			FilterType::LowShelf => {
+				let sqrt_a_alpha = 2.0 * a.sqrt() * alpha;
+				(
+					a * ((a + 1.0) - (a - 1.0) * cos_w0 + sqrt_a_alpha),
+					2.0 * a * ((a - 1.0) - (a + 1.0) * cos_w0),
+					a * ((a + 1.0) - (a - 1.0) * cos_w0 - sqrt_a_alpha),
+					(a + 1.0) + (a - 1.0) * cos_w0 + sqrt_a_alpha,
+					-2.0 * ((a - 1.0) + (a + 1.0) * cos_w0),
+					(a + 1.0) + (a - 1.0) * cos_w0 - sqrt_a_alpha,
+				)
+			},
+			FilterType::HighShelf => {
+				let sqrt_a_alpha = 2.0 * a.sqrt() * alpha;
+				(
+					a * ((a + 1.0) + (a - 1.0) * cos_w0 + sqrt_a_alpha),
+					-2.0 * a * ((a - 1.0) + (a + 1.0) * cos_w0),
+					a * ((a + 1.0) + (a - 1.0) * cos_w0 - sqrt_a_alpha),
+					(a + 1.0) - (a - 1.0) * cos_w0 + sqrt_a_alpha,
+					2.0 * ((a - 1.0) - (a + 1.0) * cos_w0),
+					(a + 1.0) - (a - 1.0) * cos_w0 - sqrt_a_alpha,
+				)
+			},
+			FilterType::Peak => (
+				1.0 + alpha * a, -2.0 * cos_w0, 1.0 - alpha * a,
+				1.0 + alpha / a, -2.0 * cos_w0, 1.0 - alpha / a,
+			),
+		};
+
+		self.b0 = b0 / a0;
+		self.b1 = b1 / a0;
+		self.b2 = b2 / a0;
+		self.a1 = a1 / a0;
+		self.a2 = a2 / a0;
+	}
+
+	/// Gain in dB this filter applies at a frequency, for drawing response curves
+	pub(crate) fn magnitude_db(&self, frequency: f32, sample_rate: f32) -> f32 {
+		let w = f32::consts::TAU * frequency / sample_rate;
+		let (sin_w, cos_w) = w.sin_cos();
+		let (sin_2w, cos_2w) = (2.0 * w).sin_cos();
+		// Evaluate both polynomials at z = e^jw
+		let num_re = self.b0 + self.b1 * cos_w + self.b2 * cos_2w;
+		let num_im = -(self.b1 * sin_w + self.b2 * sin_2w);
+		let den_re = 1.0 + self.a1 * cos_w + self.a2 * cos_2w;
+		let den_im = -(self.a1 * sin_w + self.a2 * sin_2w);
+		let magnitude_sq = (num_re * num_re + num_im * num_im) / (den_re * den_re + den_im * den_im).max(1e-12);
+		10.0 * magnitude_sq.max(1e-12).log10()
+	}
+
+	/// Change the response, call update_params() afterwards to get new coefficients
+	pub(crate) fn set_type(&mut self, filter_type: FilterType) {
+		self.filter_type = filter_type;
+	}
+
+	/// Clear the filter history
+	pub(crate) fn reset(&mut self) {
+		self.l_state = [0.0; 2];
+		self.r_state = [0.0; 2];
+	}
+
+	fn tick(&self, state: &mut [f32; 2], input: f32) -> f32 {
+		let output = self.b0 * input + state[0];
+		state[0] = self.b1 * input - self.a1 * output + state[1];
+		state[1] = self.b2 * input - self.a2 * output;
+		output
+	}
+
+	/// Filter a single channel through the left side state
+	pub(crate) fn filter_mono(&mut self, input: f32) -> f32 {
+		let mut l_state = self.l_state;
+		let filtered = self.tick(&mut l_state, input);
+		self.l_state = l_state;
+		filtered
+	}
+
+	/// Perform filtering on left and right audio using the struct
+	pub(crate) fn filter(&mut self, left: f32, right: f32) -> (f32, f32) {
+		let mut l_state = self.l_state;
+		let mut r_state = self.r_state;
+		let filtered = (self.tick(&mut l_state, left), self.tick(&mut r_state, right));
+		self.l_state = l_state;
+		self.r_state = r_state;
+		filtered
+	}
+}
+
+/// -3 dB frequency of the old low pass, two one-poles `y = a*x + (1-a)*y` in series.
+/// Each stage sits at -1.5 dB there, coefficients of 1 and up never cut below Nyquist
+pub(crate) fn one_pole_pair_cutoff_hz(coefficient: f32, sample_rate: f32) -> f32 {
+	let nyquist = sample_rate * 0.5;
+	let feedback = 1.0 - coefficient;
+	if feedback <= 0.0 {
+		return nyquist;
+	}
+	let cos_w = (1.0 + feedback * feedback - coefficient * coefficient * f32::consts::SQRT_2) / (2.0 * feedback);
+	if cos_w <= -1.0 {
+		return nyquist;
+	}
+	cos_w.min(1.0).acos() / (2.0 * f32::consts::PI) * sample_rate
+}
+
+#[derive(Enum, PartialEq, Eq, Debug, Copy, Clone)]
+pub enum FilterSlope {
+	#[name = "Slope:6dB"]
+	Db6,
+	#[name = "Slope:12dB"]
+	Db12,
+	#[name = "Slope:24dB"]
+	Db24,
+	#[name = "Slope:48dB"]
+	Db48,
+}
+
+impl FilterSlope {
+	/// Q of each biquad for a flat Butterworth cascade, the 6 dB slope is a single one pole
+	fn section_qs(&self) -> &'static [f32] {
+		match self {
+			FilterSlope::Db6 => &[BUTTERWORTH_Q],
+			FilterSlope::Db12 => &[BUTTERWORTH_Q],
+			FilterSlope::Db24 => &[0.5412, 1.3066],
+			FilterSlope::Db48 => &[0.5098, 0.6013, 0.9000, 2.5629],
+		}
+	}
+}
+
+#[derive(Enum, PartialEq, Eq, Debug, Copy, Clone)]
+pub enum FilterPlacement {
+	#[name = "Filter:Pre"]
+	PreStack,
+	#[name = "Filter:In Loop"]
+	InLoop,
+	#[name = "Filter:Post"]
+	PostStack,
+}
+
+/// Low or high pass made from up to four biquads so the slope can go from 6 to 48 dB/oct
+#[derive(Clone, Copy)]
+pub(crate) struct StereoSlopeFilter {
 	lowpass: bool,
+	slope: FilterSlope,
+	sections: [StereoBiquad; 4],
 }
 
-impl StereoFilter {
-    pub(crate) fn new(cutoff_frequency: f32, lowpass: bool) -> StereoFilter {
-        StereoFilter {
-            cutoff_frequency,
-            l_old: 0.0,
-			r_old: 0.0,
-			l_old2: 0.0,
-			r_old2: 0.0,
+impl StereoSlopeFilter {
+	pub(crate) fn new(lowpass: bool, frequency: f32, slope: FilterSlope, q: f32, sample_rate: f32) -> StereoSlopeFilter {
+		let filter_type = if lowpass { FilterType::LowPass } else { FilterType::HighPass };
+		let mut filter = StereoSlopeFilter {
 			lowpass,
-        }
-    }
+			slope,
+			sections: [StereoBiquad::new(filter_type, frequency, BUTTERWORTH_Q, 0.0, sample_rate); 4],
+		};
+		filter.update_params(frequency, slope, q, sample_rate);
+		filter
+	}
 
-	/// Update Cutoff frequency and filtertype
-	pub(crate) fn update_params(&mut self, cutoff_frequency: f32, lowpass: bool) {
-		self.lowpass = lowpass;
-		self.cutoff_frequency = if self.lowpass { cutoff_frequency } else { 1.0 - cutoff_frequency };
+	/// Update cutoff in Hz, slope and resonance, Q above Butterworth peaks the cutoff
+	pub(crate) fn update_params(&mut self, frequency: f32, slope: FilterSlope, q: f32, sample_rate: f32) {
+		if slope != self.slope {
+			// Sections that were sitting idle still hold old audio
+			self.slope = slope;
+			self.reset();
+		}
+
+		let filter_type = match (self.lowpass, slope) {
+			(true, FilterSlope::Db6) => FilterType::FirstOrderLowPass,
+			(false, FilterSlope::Db6) => FilterType::FirstOrderHighPass,
+			(true, _) => FilterType::LowPass,
+			(false, _) => FilterType::HighPass,
+		};
+
+		// Resonance goes on the last and sharpest section
+		let section_qs = slope.section_qs();
+		let resonance = q / BUTTERWORTH_Q;
+		for (index, (section, section_q)) in self.sections.iter_mut().zip(section_qs.iter()).enumerate() {
+			let section_q = if index + 1 == section_qs.len() { section_q * resonance } else { *section_q };
+			section.set_type(filter_type);
+			section.update_params(frequency, section_q, 0.0, sample_rate);
+		}
+	}
+
+	/// Clear the filter history
+	pub(crate) fn reset(&mut self) {
+		self.sections.iter_mut().for_each(StereoBiquad::reset);
+	}
+
+	/// Filter a single channel, for running inside one delay line
+	pub(crate) fn filter_mono(&mut self, input: f32) -> f32 {
+		let sections = self.slope.section_qs().len();
+		self.sections.iter_mut().take(sections).fold(input, |sample, section| section.filter_mono(sample))
 	}
 
 	/// Perform filtering on left and right audio using the struct
-    pub(crate) fn filter(&mut self, left: f32, right: f32) -> (f32,f32) {
-		let mut l_filtered;
-		let mut r_filtered;
-
-		l_filtered = (self.l_old * (1.0 - self.cutoff_frequency)) + (left * self.cutoff_frequency);
-		r_filtered = (self.r_old * (1.0 - self.cutoff_frequency)) + (right * self.cutoff_frequency);
-		self.l_old = l_filtered;
-    	self.r_old = r_filtered;
-		l_filtered = (self.l_old2 * (1.0 - self.cutoff_frequency)) + (l_filtered * self.cutoff_frequency);
-		r_filtered = (self.r_old2 * (1.0 - self.cutoff_frequency)) + (r_filtered * self.cutoff_frequency);
-		self.l_old2 = l_filtered;
-    	self.r_old2 = r_filtered;
-
-		if self.lowpass {
-			(l_filtered, r_filtered)
-		}
-		else {
-			(left - self.l_old2 - self.l_old, 
-			right - self.r_old2 - self.r_old)
-		}
-    }
+	pub(crate) fn filter(&mut self, left: f32, right: f32) -> (f32, f32) {
+		let sections = self.slope.section_qs().len();
+		self.sections.iter_mut().take(sections).fold((left, right), |(l, r), section| section.filter(l, r))
+	}
+}
+
+/// Bands of the wet EQ in order, low shelf, two peaks and a high shelf
+pub(crate) const EQ_BANDS: usize = 4;
+const EQ_BAND_TYPES: [FilterType; EQ_BANDS] = [FilterType::LowShelf, FilterType::Peak, FilterType::Peak, FilterType::HighShelf];
+
+/// Four band parametric EQ, each band is (frequency in Hz, gain in dB, Q)
+#[derive(Clone, Copy)]
+pub(crate) struct FourBandEq {
+	bands: [StereoBiquad; EQ_BANDS],
+}
+
+impl FourBandEq {
+	pub(crate) fn new(settings: [(f32, f32, f32); EQ_BANDS], sample_rate: f32) -> FourBandEq {
+		let mut eq = FourBandEq {
+			bands: EQ_BAND_TYPES.map(|filter_type| StereoBiquad::new(filter_type, 1000.0, BUTTERWORTH_Q, 0.0, sample_rate)),
+		};
+		eq.update_params(settings, sample_rate);
+		eq
+	}
+
+	/// Update every band
+	pub(crate) fn update_params(&mut self, settings: [(f32, f32, f32); EQ_BANDS], sample_rate: f32) {
+		for (band, (frequency, gain_db, q)) in self.bands.iter_mut().zip(settings) {
+			band.update_params(frequency, q, gain_db, sample_rate);
+		}
+	}
+
+	/// Clear the filter history
+	pub(crate) fn reset(&mut self) {
+		self.bands.iter_mut().for_each(StereoBiquad::reset);
+	}
+
+	/// Total gain in dB of all bands at a frequency
+	pub(crate) fn magnitude_db(&self, frequency: f32, sample_rate: f32) -> f32 {
+		self.bands.iter().map(|band| band.magnitude_db(frequency, sample_rate)).sum()
+	}
+
+	/// Perform filtering on left and right audio using the struct
+	pub(crate) fn filter(&mut self, left: f32, right: f32) -> (f32, f32) {
+		self.bands.iter_mut().fold((left, right), |(l, r), band| band.filter(l, r))
+	}
+}
+
+/// Pivot of the tilt EQ, the lows go one way and the highs the other
+const TILT_PIVOT_HZ: f32 = 1000.0;
+/// Gentle shelves so the two halves blend into one slope through the pivot
+const TILT_Q: f32 = 0.5;
+
+/// Tilt EQ from a low and high shelf meeting at the pivot, positive tilt is brighter
+#[derive(Clone, Copy)]
+pub(crate) struct TiltEq {
+	low_shelf: StereoBiquad,
+	high_shelf: StereoBiquad,
+}
+
+impl TiltEq {
+	pub(crate) fn new(tilt_db: f32, sample_rate: f32) -> TiltEq {
+		TiltEq {
+			low_shelf: StereoBiquad::new(FilterType::LowShelf, TILT_PIVOT_HZ, TILT_Q, -tilt_db * 0.5, sample_rate),
+			high_shelf: StereoBiquad::new(FilterType::HighShelf, TILT_PIVOT_HZ, TILT_Q, tilt_db * 0.5, sample_rate),
+		}
+	}
+
+	/// Update the tilt in dB, half goes to each shelf
+	pub(crate) fn update_params(&mut self, tilt_db: f32, sample_rate: f32) {
+		self.low_shelf.update_params(TILT_PIVOT_HZ, TILT_Q, -tilt_db * 0.5, sample_rate);
+		self.high_shelf.update_params(TILT_PIVOT_HZ, TILT_Q, tilt_db * 0.5, sample_rate);
+	}
+
+	/// Clear the filter history
+	pub(crate) fn reset(&mut self) {
+		self.low_shelf.reset();
+		self.high_shelf.reset();
+	}
+
+	/// Perform filtering on left and right audio using the struct
+	pub(crate) fn filter(&mut self, left: f32, right: f32) -> (f32, f32) {
+		let (low_l, low_r) = self.low_shelf.filter(left, right);
+		self.high_shelf.filter(low_l, low_r)
+	}
+}
+
+/// Darkens a little more every trip around a feedback loop, only ever cuts so the loop can't run away
+#[derive(Clone, Copy)]
+pub(crate) struct AgeFilter {
+	enabled: bool,
+	high_shelf: StereoBiquad,
+}
+
+impl AgeFilter {
+	pub(crate) fn new(sample_rate: f32) -> AgeFilter {
+		AgeFilter {
+			enabled: false,
+			high_shelf: StereoBiquad::new(FilterType::HighShelf, TILT_PIVOT_HZ, TILT_Q, 0.0, sample_rate),
+		}
+	}
+
+	/// Update how many dB the highs lose on each pass
+	pub(crate) fn update_params(&mut self, cut_per_pass_db: f32, sample_rate: f32) {
+		let enabled = cut_per_pass_db > 0.0;
+		if enabled && !self.enabled {
+			self.high_shelf.reset();
+		}
+		self.enabled = enabled;
+		self.high_shelf.update_params(TILT_PIVOT_HZ, TILT_Q, -cut_per_pass_db, sample_rate);
+	}
+
+	/// Clear the filter history
+	pub(crate) fn reset(&mut self) {
+		self.high_shelf.reset();
+	}
+
+	/// Filter a single channel, passes straight through when there's no age
+	pub(crate) fn filter_mono(&mut self, input: f32) -> f32 {
+		if self.enabled {
+			self.high_shelf.filter_mono(input)
+		} else {
+			input
+		}
+	}
+}
+
+// Butterworth state variable filter section, two in a row make one side of a Linkwitz-Riley crossover
+#[derive(Clone, Copy, Default)]
+struct SvfState {
+	ic1eq: f32,
+	ic2eq: f32,
+}
+
+impl SvfState {
+	/// Run one sample and return the lowpass and highpass outputs
+	fn tick(&mut self, input: f32, k: f32, a1: f32, a2: f32, a3: f32) -> (f32, f32) {
+		let v3 = input - self.ic2eq;
+		let v1 = a1 * self.ic1eq + a2 * v3;
+		let v2 = self.ic2eq + a2 * self.ic1eq + a3 * v3;
+		self.ic1eq = 2.0 * v1 - self.ic1eq;
+		self.ic2eq = 2.0 * v2 - self.ic2eq;
+		(v2, input - k * v1 - v2)
+	}
+}
+
+/// Stereo 4th order Linkwitz-Riley crossover, the low and high bands sum back to a flat allpass
+#[derive(Clone, Copy)]
+pub(crate) struct Crossover {
+	k: f32,
+	a1: f32,
+	a2: f32,
+	a3: f32,
+	low_l: [SvfState; 2],
+	low_r: [SvfState; 2],
+	high_l: [SvfState; 2],
+	high_r: [SvfState; 2],
+}
+
+impl Crossover {
+	pub(crate) fn new(cutoff_hz: f32, sample_rate: f32) -> Crossover {
+		let mut crossover = Crossover {
+			k: f32::consts::SQRT_2,
+			a1: 0.0,
+			a2: 0.0,
+			a3: 0.0,
+			low_l: [SvfState::default(); 2],
+			low_r: [SvfState::default(); 2],
+			high_l: [SvfState::default(); 2],
+			high_r: [SvfState::default(); 2],
+		};
+		crossover.update_params(cutoff_hz, sample_rate);
+		crossover
+	}
+
+	/// Update the split frequency in Hz
+	pub(crate) fn update_params(&mut self, cutoff_hz: f32, sample_rate: f32) {
+		// Keep the cutoff under nyquist so tan() stays finite
+		let g = (f32::consts::PI * cutoff_hz.min(sample_rate * 0.49) / sample_rate).tan();
+		self.a1 = 1.0 / (1.0 + g * (g + self.k));
+		self.a2 = g * self.a1;
+		self.a3 = g * self.a2;
+	}
+
+	/// Clear the filter history
+	pub(crate) fn reset(&mut self) {
+		for state in self.low_l.iter_mut().chain(self.low_r.iter_mut())
+			.chain(self.high_l.iter_mut()).chain(self.high_r.iter_mut()) {
+			*state = SvfState::default();
+		}
+	}
+
+	/// Split left and right audio into ((low_l, low_r), (high_l, high_r))
+	pub(crate) fn split(&mut self, left: f32, right: f32) -> ((f32, f32), (f32, f32)) {
+		let (k, a1, a2, a3) = (self.k, self.a1, self.a2, self.a3);
+		let low_l = self.low_l.iter_mut().fold(left, |sample, state| state.tick(sample, k, a1, a2, a3).0);
+		let low_r = self.low_r.iter_mut().fold(right, |sample, state| state.tick(sample, k, a1, a2, a3).0);
+		let high_l = self.high_l.iter_mut().fold(left, |sample, state| state.tick(sample, k, a1, a2, a3).1);
+		let high_r = self.high_r.iter_mut().fold(right, |sample, state| state.tick(sample, k, a1, a2, a3).1);
+		((low_l, low_r), (high_l, high_r))
+	}
+
+	/// Same phase shift as a split without separating anything, keeps other bands lined up with this one
+	pub(crate) fn allpass(&mut self, left: f32, right: f32) -> (f32, f32) {
+		let ((low_l, low_r), (high_l, high_r)) = self.split(left, right);
+		(low_l + high_l, low_r + high_r)
+	}
 }
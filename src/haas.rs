@@ -0,0 +1,136 @@
+// Haas offset for the wet, a real inter-channel delay in ms or synced to the host tempo
+// by Ardura
+
+use nih_plug::prelude::Enum;
+
+// Longest offset either mode can ask for, note values past this get clamped
+const MAX_HAAS_MS: f32 = 1000.0;
+// Delay, polarity and side changes glide over about this long instead of clicking
+const HAAS_GLIDE_MS: f32 = 50.0;
+
+#[derive(Enum, PartialEq, Eq, Debug, Copy, Clone)]
+pub enum HaasMode {
+    #[name = "Haas:ms"]
+    Milliseconds,
+    #[name = "Haas:Note"]
+    Note,
+}
+
+#[derive(Enum, PartialEq, Eq, Debug, Copy, Clone)]
+pub enum HaasNote {
+    #[name = "Note:1/128"]
+    OneTwentyEighth,
+    #[name = "Note:1/64"]
+    SixtyFourth,
+    #[name = "Note:1/32"]
+    ThirtySecond,
+    #[name = "Note:1/32T"]
+    ThirtySecondTriplet,
+    #[name = "Note:1/16"]
+    Sixteenth,
+    #[name = "Note:1/16T"]
+    SixteenthTriplet,
+    #[name = "Note:1/16D"]
+    SixteenthDotted,
+    #[name = "Note:1/8"]
+    Eighth,
+    #[name = "Note:1/4"]
+    Quarter,
+}
+
+impl HaasNote {
+    // Length in quarter note beats
+    pub fn beats(&self) -> f32 {
+        match self {
+            HaasNote::OneTwentyEighth => 1.0 / 32.0,
+            HaasNote::SixtyFourth => 1.0 / 16.0,
+            HaasNote::ThirtySecond => 1.0 / 8.0,
+            HaasNote::ThirtySecondTriplet => 1.0 / 12.0,
+            HaasNote::Sixteenth => 1.0 / 4.0,
+            HaasNote::SixteenthTriplet => 1.0 / 6.0,
+            HaasNote::SixteenthDotted => 3.0 / 8.0,
+            HaasNote::Eighth => 1.0 / 2.0,
+            HaasNote::Quarter => 1.0,
+        }
+    }
+}
+
+// One delay line per side so swapping sides just glides one down to zero and the other up
+struct HaasLine {
+    buffer: Vec<f32>,
+    delay: f32,
+    gain: f32,
+}
+
+impl HaasLine {
+    fn new(length: usize) -> Self {
+        HaasLine {
+            buffer: vec![0.0; length],
+            delay: 0.0,
+            gain: 1.0,
+        }
+    }
+
+    // Fractional read with linear interpolation so the delay can move without stepping
+    fn process(&mut self, input: f32, write_index: usize) -> f32 {
+        let length = self.buffer.len();
+        self.buffer[write_index] = input;
+        let read_position = write_index as f32 - self.delay + length as f32;
+        let index = read_position.floor() as usize;
+        let fraction = read_position.fract();
+        let a = self.buffer[index % length];
+        let b = self.buffer[(index + 1) % length];
+        (a + (b - a) * fraction) * self.gain
+    }
+}
+
+pub(crate) struct StereoHaas {
+    left: HaasLine,
+    right: HaasLine,
+    write_index: usize,
+    max_delay: f32,
+    glide_weight: f32,
+}
+
+impl StereoHaas {
+    pub(crate) fn new(sample_rate: f32) -> Self {
+        let max_delay = (MAX_HAAS_MS * 0.001 * sample_rate).ceil();
+        // Room for the interpolation to read one past the longest delay
+        let length = max_delay as usize + 2;
+        StereoHaas {
+            left: HaasLine::new(length),
+            right: HaasLine::new(length),
+            write_index: 0,
+            max_delay,
+            glide_weight: (-1.0 / (HAAS_GLIDE_MS * 0.001 * sample_rate)).exp(),
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        for line in [&mut self.left, &mut self.right] {
+            line.buffer.iter_mut().for_each(|sample| *sample = 0.0);
+        }
+        self.write_index = 0;
+    }
+
+    // Current delay on whichever side is delayed, for the tail estimate
+    pub(crate) fn delay_samples(&self) -> f32 {
+        self.left.delay.max(self.right.delay)
+    }
+
+    // The right side is delayed unless swapped, invert flips the polarity of whichever side is delayed
+    pub(crate) fn process(&mut self, input_l: f32, input_r: f32, delay_samples: f32, invert: bool, swap: bool) -> (f32, f32) {
+        let delay = delay_samples.clamp(0.0, self.max_delay);
+        let delayed_gain = if invert { -1.0 } else { 1.0 };
+        let (left_target, right_target) = if swap { ((delay, delayed_gain), (0.0, 1.0)) } else { ((0.0, 1.0), (delay, delayed_gain)) };
+        for (line, (target_delay, target_gain)) in [(&mut self.left, left_target), (&mut self.right, right_target)] {
+            line.delay = target_delay + (line.delay - target_delay) * self.glide_weight;
+            line.gain = target_gain + (line.gain - target_gain) * self.glide_weight;
+        }
+
+        let output_l = self.left.process(input_l, self.write_index);
+        let output_r = self.right.process(input_r, self.write_index);
+        self.write_index = (self.write_index + 1) % self.left.buffer.len();
+        (output_l, output_r)
+    }
+}
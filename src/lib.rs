@@ -1,18 +1,33 @@
 #![allow(non_snake_case)]
 mod ui_knob;
+mod ui_eq;
 mod reverb;
 mod filters;
 mod ducking;
-use ducking::Ducking;
-use nih_plug::{prelude::*};
+mod meters;
+mod saturation;
+mod safety;
+mod random;
+mod haas;
+mod envelope;
+use ducking::{DetectionMode, KeySource, Lookahead, MultibandDucking, MAX_DUCK_BANDS};
+use nih_plug::{prelude::*, wrapper::state::ParamValue};
 use nih_plug_egui::{create_egui_editor, egui::{self, Color32, Rect, Rounding, RichText, FontId, Pos2}, EguiState, widgets::ParamSlider};
 mod CustomParamSlider;
 use CustomParamSlider::ParamSlider as OtherParamSlider;
+use filters::{FilterPlacement, FilterSlope, FourBandEq, StereoSlopeFilter, TiltEq, BUTTERWORTH_Q, EQ_BANDS};
+use meters::Meters;
 use reverb::{Reverb, ReverbType};
+use saturation::{SaturationPlacement, SaturationType, Saturator};
+use safety::{LimiterMode, OutputLimiter, RunawayGuard};
+use random::XorShift32;
+use haas::{HaasMode, HaasNote, StereoHaas};
+use envelope::{EnvelopeDirection, EnvelopeFollower};
+use ui_eq::EqBandParams;
 use ui_knob::lerp;
 use std::f32;
-use std::{sync::{Arc}, ops::RangeInclusive, collections::VecDeque};
-use rand::prelude::*;
+use std::{sync::{Arc, atomic::{AtomicBool, Ordering}}, ops::RangeInclusive, collections::VecDeque};
+use atomic_float::AtomicF32;
 
 /***************************************************************************
  * Canopy Reverb by Ardura
@@ -27,12 +42,88 @@ const A_KNOB_INSIDE_COLOR: Color32 = Color32::from_rgb(233, 109, 46);
 const A_KNOB_OUTSIDE_COLOR2: Color32 = Color32::from_rgb(0, 74, 76);
 
 
+// Pages of controls in the editor
+#[derive(PartialEq, Clone, Copy)]
+enum EditorTab {
+    Reverb,
+    Ducking,
+    Bands,
+    Tone,
+    Stereo,
+    Drive,
+    Envelope,
+}
+
 // Plugin sizing
-const WIDTH: u32 = 460;
+const WIDTH: u32 = 560;
 const HEIGHT: u32 = 410;
 
+// Parameter limits that decide how much reverb buffer we allocate up front
+const MAX_REVERB_STACK: usize = 12;
+const MAX_REVERB_DELAY: i32 = 1200;
+const MAX_REVERB_STEPS: i32 = 36;
+
+// Wet filter ranges in Hz, older presets saved coefficients below these
+const MIN_HIGH_PASS_HZ: f32 = 20.0;
+const MIN_LOW_PASS_HZ: f32 = 1000.0;
+const MAX_LOW_PASS_HZ: f32 = 20000.0;
+
+// How long switching send mode takes to fade over
+const SEND_FADE_MS: f32 = 50.0;
+
+// Sample rate old filter coefficients are converted at when loading presets
+const MIGRATION_SAMPLE_RATE: f32 = 44100.0;
+
+// Auto gain glides to a new target over roughly this long
+const AUTO_GAIN_SMOOTH_MS: f32 = 100.0;
+// Auto gain leaves a single stack at the default decay untouched
+const AUTO_GAIN_REFERENCE_DECAY: f32 = 0.437;
+// Limits on how far auto gain will go either way
+const AUTO_GAIN_MIN_DB: f32 = -60.0;
+const AUTO_GAIN_MAX_DB: f32 = 12.0;
+
+// Full envelope depth moves the high cut this many octaves either way
+const ENV_CUTOFF_OCTAVES: f32 = 3.0;
+// The modulated high cut is picked up every this many samples instead of every sample
+const ENV_CUTOFF_INTERVAL: u32 = 32;
+
+// Full age takes this many dB off the highs on every trip around a stack's feedback
+const MAX_AGE_CUT_DB: f32 = 3.0;
+
+// Right side stacks run this much longer when widening mono so the two sides don't match
+const MONO_WIDEN_DETUNE: f32 = 1.07;
+
+// Surround channels past the front pair, 7.1 being the largest layout
+const MAX_SURROUND_CHANNELS: usize = 6;
+// The LFE sits at index 3 in 5.1 and 7.1 and never gets any reverb
+const LFE_CHANNEL: usize = 3;
+// Each surround channel stretches its delays differently so no two channels share a tap pattern,
+// none of them are 1.0 or the mono widen detune so they never line up with the front stacks either
+const SURROUND_DETUNE: [f32; MAX_SURROUND_CHANNELS] = [1.031, 1.053, 1.113, 1.137, 1.173, 1.191];
+// Largest of the above, the surround stacks reserve for this much stretch
+const MAX_SURROUND_DETUNE: f32 = 1.191;
+
+// First order AmbiX is W, Y, Z, X in ACN order with SN3D normalization
+const AMBIX_LAYOUT: &str = "AmbiX";
+const AMBIX_CHANNELS: usize = 4;
+// Stacks alternate this far above and below the horizon (about 20 degrees)
+const AMBIX_ELEVATION: f32 = 0.35;
+
+// Stereo sidechain input for keying the ducking from another track
+const SIDECHAIN_PORTS: &[NonZeroU32] = &[new_nonzero_u32(2)];
+const SIDECHAIN_NAMES: &[&str] = &["Sidechain"];
+
+// Longest ducking lookahead, every input channel gets delayed plus one slot for the surround feed
+const MAX_LOOKAHEAD_MS: f32 = 20.0;
+const LOOKAHEAD_FEED_SLOT: usize = MAX_SURROUND_CHANNELS + 2;
+
 pub struct Gain {
     params: Arc<GainParams>,
+    sample_rate: f32,
+    num_input_channels: usize,
+    num_output_channels: usize,
+    ambisonic_output: bool,
+    active_stacks: usize,
     reverb_l_array: Vec<reverb::Reverb>,
     reverb_r_array: Vec<reverb::Reverb>,
     prev_reverb_steps: i32,
@@ -45,13 +136,60 @@ pub struct Gain {
     prev_processed_out_r: f32,
     prev_low_cut: f32,
     prev_high_cut: f32,
-    prev_sc_threshold: f32,
-    filter_lowpass: filters::StereoFilter,
-    filter_highpass: filters::StereoFilter,
+    prev_duck_thresholds: [f32; MAX_DUCK_BANDS],
+    prev_duck_attack: f32,
+    prev_duck_release: f32,
+    prev_duck_hold: f32,
+    prev_duck_curves: [(f32, f32, f32); MAX_DUCK_BANDS],
+    prev_duck_detection: (DetectionMode, f32),
+    prev_duck_crossovers: (f32, f32),
+    prev_filter_shape: (FilterSlope, f32, FilterSlope, f32),
+    prev_filter_placement: FilterPlacement,
+    filter_lowpass: StereoSlopeFilter,
+    filter_highpass: StereoSlopeFilter,
+    prev_eq_settings: [(f32, f32, f32); EQ_BANDS],
+    wet_eq: FourBandEq,
+    surround_eq: Vec<FourBandEq>,
+    ambi_eq: [FourBandEq; 2],
+    prev_tilt: f32,
+    prev_age: f32,
+    prev_saturation: (SaturationType, f32, f32, SaturationPlacement),
+    input_saturator: Saturator,
+    wet_tilt: TiltEq,
+    surround_tilt: Vec<TiltEq>,
+    ambi_tilt: [TiltEq; 2],
     prev_rand_offset: f32,
-    prev_width_offset: i32,
-    ducking_l: Ducking,
-    ducking_r: Ducking,
+    // Delay times get generated in here before being copied into each stack
+    step_scratch: VecDeque<i32>,
+    rng: XorShift32,
+    haas: StereoHaas,
+    envelope: EnvelopeFollower,
+    prev_decay_mod: f32,
+    prev_mod_high_cut: f32,
+    cutoff_counter: u32,
+    prev_seed: i32,
+    prev_mono_widen: bool,
+    prev_mid_side: (bool, f32, f32),
+    ducking: MultibandDucking,
+    lookahead: Lookahead,
+    meters: Arc<Meters>,
+    // Lets the editor grey out the AmbiX only controls
+    ambisonic_active: Arc<AtomicBool>,
+    // Sample rate for the EQ curve, zero until initialize()
+    editor_sample_rate: Arc<AtomicF32>,
+    meter_decay_weight: f32,
+    limiter: OutputLimiter,
+    auto_gain: f32,
+    auto_gain_target: f32,
+    auto_gain_weight: f32,
+    runaway_guard: RunawayGuard,
+    send_fade: f32,
+    surround_arrays: Vec<Vec<reverb::Reverb>>,
+    surround_filters: Vec<(StereoSlopeFilter, StereoSlopeFilter)>,
+    surround_dc: Vec<(f32, f32)>,
+    ambi_directions: [[f32; 3]; MAX_REVERB_STACK],
+    ambi_filters: [(StereoSlopeFilter, StereoSlopeFilter); 2],
+    ambi_dc: [(f32, f32); AMBIX_CHANNELS],
 }
 
 #[derive(Params)]
@@ -79,9 +217,95 @@ struct GainParams {
     #[id = "reverb_width"]
     pub reverb_width: FloatParam,
 
+    #[id = "mid_side"]
+    pub mid_side: BoolParam,
+
+    #[id = "mid_decay"]
+    pub mid_decay: FloatParam,
+
+    #[id = "side_decay"]
+    pub side_decay: FloatParam,
+
+    #[id = "mid_level"]
+    pub mid_level: FloatParam,
+
+    #[id = "side_level"]
+    pub side_level: FloatParam,
+
+    #[id = "saturation_type"]
+    pub saturation_type: EnumParam<SaturationType>,
+
+    #[id = "saturation_placement"]
+    pub saturation_placement: EnumParam<SaturationPlacement>,
+
+    #[id = "saturation_drive"]
+    pub saturation_drive: FloatParam,
+
+    #[id = "saturation_character"]
+    pub saturation_character: FloatParam,
+
+    #[id = "limiter_mode"]
+    pub limiter_mode: EnumParam<LimiterMode>,
+
+    #[id = "limiter_ceiling"]
+    pub limiter_ceiling: FloatParam,
+
+    #[id = "runaway_protection"]
+    pub runaway_protection: BoolParam,
+
+    #[id = "auto_gain"]
+    pub auto_gain: BoolParam,
+
+    #[id = "env_direction"]
+    pub env_direction: EnumParam<EnvelopeDirection>,
+
+    #[id = "env_attack"]
+    pub env_attack: FloatParam,
+
+    #[id = "env_release"]
+    pub env_release: FloatParam,
+
+    #[id = "env_decay_depth"]
+    pub env_decay_depth: FloatParam,
+
+    #[id = "env_mix_depth"]
+    pub env_mix_depth: FloatParam,
+
+    #[id = "env_cutoff_depth"]
+    pub env_cutoff_depth: FloatParam,
+
+    #[id = "tilt"]
+    pub tilt: FloatParam,
+
+    #[id = "age"]
+    pub age: FloatParam,
+
+    // Used in place of reverb_width for AmbiX output
+    #[id = "ambi_spread"]
+    pub ambi_spread: FloatParam,
+
     #[id = "width_random"]
     pub width_random: FloatParam,
 
+    #[id = "random_seed"]
+    pub random_seed: IntParam,
+
+    #[id = "haas_mode"]
+    pub haas_mode: EnumParam<HaasMode>,
+
+    #[id = "haas_time"]
+    pub haas_time: FloatParam,
+
+    #[id = "haas_note"]
+    pub haas_note: EnumParam<HaasNote>,
+
+    #[id = "haas_invert"]
+    pub haas_invert: BoolParam,
+
+    #[id = "haas_swap"]
+    pub haas_swap: BoolParam,
+
+    // Deprecated, only kept so old automation still lands somewhere
     #[id = "width_offset"]
     pub width_offset: IntParam,
 
@@ -91,25 +315,150 @@ struct GainParams {
     #[id = "reverb_high_cut"]
     pub reverb_high_cut: FloatParam,
 
+    #[id = "filter_placement"]
+    pub filter_placement: EnumParam<FilterPlacement>,
+
+    #[id = "reverb_low_cut_slope"]
+    pub reverb_low_cut_slope: EnumParam<FilterSlope>,
+
+    #[id = "reverb_high_cut_slope"]
+    pub reverb_high_cut_slope: EnumParam<FilterSlope>,
+
+    #[id = "reverb_low_cut_q"]
+    pub reverb_low_cut_q: FloatParam,
+
+    #[id = "reverb_high_cut_q"]
+    pub reverb_high_cut_q: FloatParam,
+
+    #[id = "eq_low_freq"]
+    pub eq_low_freq: FloatParam,
+
+    #[id = "eq_low_gain"]
+    pub eq_low_gain: FloatParam,
+
+    #[id = "eq_low_q"]
+    pub eq_low_q: FloatParam,
+
+    #[id = "eq_mid1_freq"]
+    pub eq_mid1_freq: FloatParam,
+
+    #[id = "eq_mid1_gain"]
+    pub eq_mid1_gain: FloatParam,
+
+    #[id = "eq_mid1_q"]
+    pub eq_mid1_q: FloatParam,
+
+    #[id = "eq_mid2_freq"]
+    pub eq_mid2_freq: FloatParam,
+
+    #[id = "eq_mid2_gain"]
+    pub eq_mid2_gain: FloatParam,
+
+    #[id = "eq_mid2_q"]
+    pub eq_mid2_q: FloatParam,
+
+    #[id = "eq_high_freq"]
+    pub eq_high_freq: FloatParam,
+
+    #[id = "eq_high_gain"]
+    pub eq_high_gain: FloatParam,
+
+    #[id = "eq_high_q"]
+    pub eq_high_q: FloatParam,
+
     #[id = "reverb_lock"]
     pub reverb_lock: IntParam,
 
+    #[id = "mono_widen"]
+    pub mono_widen: BoolParam,
+
     #[id = "reverb_sidechain"]
     pub reverb_sidechain: FloatParam,
 
+    #[id = "key_source"]
+    pub key_source: EnumParam<KeySource>,
+
+    #[id = "duck_attack"]
+    pub duck_attack: FloatParam,
+
+    #[id = "duck_release"]
+    pub duck_release: FloatParam,
+
+    #[id = "duck_hold"]
+    pub duck_hold: FloatParam,
+
+    #[id = "duck_ratio"]
+    pub duck_ratio: FloatParam,
+
+    #[id = "duck_range"]
+    pub duck_range: FloatParam,
+
+    #[id = "duck_knee"]
+    pub duck_knee: FloatParam,
+
+    #[id = "duck_link"]
+    pub duck_link: FloatParam,
+
+    #[id = "duck_detection"]
+    pub duck_detection: EnumParam<DetectionMode>,
+
+    #[id = "duck_lookahead"]
+    pub duck_lookahead: FloatParam,
+
+    #[id = "duck_bands"]
+    pub duck_bands: IntParam,
+
+    #[id = "duck_low_crossover"]
+    pub duck_low_crossover: FloatParam,
+
+    #[id = "duck_high_crossover"]
+    pub duck_high_crossover: FloatParam,
+
+    #[id = "duck_low_threshold"]
+    pub duck_low_threshold: FloatParam,
+
+    #[id = "duck_low_depth"]
+    pub duck_low_depth: FloatParam,
+
+    #[id = "duck_mid_threshold"]
+    pub duck_mid_threshold: FloatParam,
+
+    #[id = "duck_mid_depth"]
+    pub duck_mid_depth: FloatParam,
+
+    #[id = "duck_high_threshold"]
+    pub duck_high_threshold: FloatParam,
+
+    #[id = "duck_high_depth"]
+    pub duck_high_depth: FloatParam,
+
     #[id = "output_gain"]
     pub output_gain: FloatParam,
 
     #[id = "dry_wet"]
     pub dry_wet: FloatParam,
+
+    #[id = "send_mode"]
+    pub send_mode: BoolParam,
+
+    #[id = "dry_level"]
+    pub dry_level: FloatParam,
+
+    #[id = "wet_level"]
+    pub wet_level: FloatParam,
 }
 
 impl Default for Gain {
     fn default() -> Self {
         Self {
             params: Arc::new(GainParams::default()),
-            reverb_l_array: (0..1).map(|_| reverb::Reverb::new(VecDeque::from(vec![0; 400]),0.6,400).clone()).collect(),
-            reverb_r_array: (0..1).map(|_| reverb::Reverb::new(VecDeque::from(vec![0; 400]),0.6,400).clone()).collect(),
+            sample_rate: 44100.0,
+            num_input_channels: 2,
+            num_output_channels: 2,
+            ambisonic_output: false,
+            active_stacks: 0,
+            reverb_l_array: (0..MAX_REVERB_STACK).map(|_| reverb::Reverb::new(VecDeque::from(vec![0; 400]),0.6,400)).collect(),
+            reverb_r_array: (0..MAX_REVERB_STACK).map(|_| reverb::Reverb::new(VecDeque::from(vec![0; 400]),0.6,400)).collect(),
             prev_reverb_steps: 0,
             prev_reverb_alg: ReverbType::ExpSwirl,
             prev_reverb_delay: 0,
@@ -121,12 +470,59 @@ impl Default for Gain {
             prev_low_cut: 0.0,
             prev_high_cut: 0.0,
             prev_rand_offset: 0.0,
-            prev_width_offset: 0,
-            prev_sc_threshold: 0.0,
-            filter_lowpass: filters::StereoFilter::new(1.0, true),
-            filter_highpass: filters::StereoFilter::new(0.5, false),
-            ducking_l: Ducking::new(0.2, 0.1, 0.1,  44100.0),
-            ducking_r: Ducking::new(0.2, 0.1, 0.1, 44100.0),
+            step_scratch: VecDeque::with_capacity(MAX_REVERB_STEPS as usize),
+            rng: XorShift32::new(1),
+            haas: StereoHaas::new(44100.0),
+            envelope: EnvelopeFollower::new(10.0, 300.0, 44100.0),
+            prev_decay_mod: 0.0,
+            prev_mod_high_cut: 0.0,
+            cutoff_counter: 0,
+            prev_seed: 1,
+            prev_mono_widen: false,
+            prev_mid_side: (false, 0.0, 0.0),
+            prev_duck_thresholds: [0.0; MAX_DUCK_BANDS],
+            prev_duck_attack: 0.0,
+            prev_duck_release: 0.0,
+            prev_duck_hold: 0.0,
+            prev_duck_curves: [(0.0, 0.0, 0.0); MAX_DUCK_BANDS],
+            prev_duck_detection: (DetectionMode::Peak, 1.0),
+            prev_duck_crossovers: (0.0, 0.0),
+            prev_filter_shape: (FilterSlope::Db12, BUTTERWORTH_Q, FilterSlope::Db12, BUTTERWORTH_Q),
+            prev_filter_placement: FilterPlacement::PostStack,
+            filter_lowpass: StereoSlopeFilter::new(true, 18000.0, FilterSlope::Db12, BUTTERWORTH_Q, 44100.0),
+            filter_highpass: StereoSlopeFilter::new(false, 30.0, FilterSlope::Db12, BUTTERWORTH_Q, 44100.0),
+            prev_eq_settings: [(0.0, 0.0, 0.0); EQ_BANDS],
+            wet_eq: FourBandEq::new([(1000.0, 0.0, BUTTERWORTH_Q); EQ_BANDS], 44100.0),
+            surround_eq: Vec::new(),
+            ambi_eq: [FourBandEq::new([(1000.0, 0.0, BUTTERWORTH_Q); EQ_BANDS], 44100.0); 2],
+            prev_tilt: 0.0,
+            prev_age: 0.0,
+            prev_saturation: (SaturationType::Off, 0.0, 0.0, SaturationPlacement::Input),
+            input_saturator: Saturator::off(),
+            wet_tilt: TiltEq::new(0.0, 44100.0),
+            surround_tilt: Vec::new(),
+            ambi_tilt: [TiltEq::new(0.0, 44100.0); 2],
+            ducking: MultibandDucking::new(0.2, 10.0, 250.0, 0.0, 44100.0),
+            lookahead: Lookahead::new(0, 0),
+            meters: Arc::new(Meters::default()),
+            ambisonic_active: Arc::new(AtomicBool::new(false)),
+            editor_sample_rate: Arc::new(AtomicF32::new(0.0)),
+            meter_decay_weight: Meters::decay_weight(44100.0),
+            limiter: OutputLimiter::new(44100.0),
+            auto_gain: 1.0,
+            auto_gain_target: 1.0,
+            auto_gain_weight: 0.0,
+            runaway_guard: RunawayGuard::new(44100.0),
+            send_fade: 0.0,
+            surround_arrays: Vec::new(),
+            surround_filters: Vec::new(),
+            surround_dc: Vec::new(),
+            ambi_directions: [[1.0, 0.0, 0.0]; MAX_REVERB_STACK],
+            ambi_filters: [(
+                StereoSlopeFilter::new(true, 18000.0, FilterSlope::Db12, BUTTERWORTH_Q, 44100.0),
+                StereoSlopeFilter::new(false, 30.0, FilterSlope::Db12, BUTTERWORTH_Q, 44100.0),
+            ); 2],
+            ambi_dc: [(0.0, 0.0); AMBIX_CHANNELS],
         }
     }
 }
@@ -139,7 +535,7 @@ impl Default for GainParams {
             reverb_stack: IntParam::new(
                 "Stack",
                 4,
-                IntRange::Linear { min: 1, max: 12 },
+                IntRange::Linear { min: 1, max: MAX_REVERB_STACK as i32 },
             )
             .with_smoother(SmoothingStyle::Linear(30.0))
             .with_unit(" Stack"),
@@ -154,7 +550,7 @@ impl Default for GainParams {
             reverb_delay: IntParam::new(
                 "Reverb Delay",
                 954,
-                IntRange::Linear { min: 100, max: 1200 },
+                IntRange::Linear { min: 100, max: MAX_REVERB_DELAY },
             )
             .with_smoother(SmoothingStyle::Linear(50.0))
             .with_unit(" ms Delay"),
@@ -172,77 +568,673 @@ impl Default for GainParams {
             .with_value_to_string(formatters::v2s_f32_rounded(3))
             .with_unit(" Decay"),
 
-            reverb_width: FloatParam::new(
-                "Reverb Width",
-                0.83,
-                FloatRange::Linear { min: 0.0, max: 10.0 },
+            reverb_width: FloatParam::new(
+                "Reverb Width",
+                0.83,
+                FloatRange::Linear { min: 0.0, max: 10.0 },
+            )
+            .with_smoother(SmoothingStyle::Linear(30.0))
+            .with_value_to_string(formatters::v2s_f32_rounded(2))
+            .with_unit(" Width"),
+
+            // Left stacks carry the mid and right stacks the side, each with their own decay
+            mid_side: BoolParam::new("Mid/Side", false),
+
+            mid_decay: FloatParam::new(
+                "Mid Decay",
+                0.437,
+                FloatRange::Skewed {
+                    min: 0.0,
+                    max: 0.999,
+                    factor: 0.7,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(30.0))
+            .with_value_to_string(formatters::v2s_f32_rounded(3))
+            .with_unit(" Mid Decay"),
+
+            side_decay: FloatParam::new(
+                "Side Decay",
+                0.437,
+                FloatRange::Skewed {
+                    min: 0.0,
+                    max: 0.999,
+                    factor: 0.7,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(30.0))
+            .with_value_to_string(formatters::v2s_f32_rounded(3))
+            .with_unit(" Side Decay"),
+
+            mid_level: FloatParam::new(
+                "Mid Level",
+                util::db_to_gain(0.0),
+                FloatRange::Skewed {
+                    min: util::db_to_gain(-48.0),
+                    max: util::db_to_gain(6.0),
+                    factor: FloatRange::gain_skew_factor(-48.0, 6.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" dB Mid")
+            .with_value_to_string(formatters::v2s_f32_gain_to_db(1))
+            .with_string_to_value(formatters::s2v_f32_gain_to_db()),
+
+            side_level: FloatParam::new(
+                "Side Level",
+                util::db_to_gain(0.0),
+                FloatRange::Skewed {
+                    min: util::db_to_gain(-48.0),
+                    max: util::db_to_gain(6.0),
+                    factor: FloatRange::gain_skew_factor(-48.0, 6.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" dB Side")
+            .with_value_to_string(formatters::v2s_f32_gain_to_db(1))
+            .with_string_to_value(formatters::s2v_f32_gain_to_db()),
+
+            // Off by default so old presets sound the same
+            saturation_type: EnumParam::new("Saturation", SaturationType::Off),
+
+            saturation_placement: EnumParam::new("Saturation Placement", SaturationPlacement::Feedback),
+
+            saturation_drive: FloatParam::new(
+                "Drive",
+                6.0,
+                FloatRange::Linear { min: 0.0, max: 24.0 },
+            )
+            .with_smoother(SmoothingStyle::Linear(30.0))
+            .with_value_to_string(formatters::v2s_f32_rounded(1))
+            .with_unit(" dB Drive"),
+
+            saturation_character: FloatParam::new(
+                "Character",
+                1.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_smoother(SmoothingStyle::Linear(30.0))
+            .with_unit("% Character")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+
+            limiter_mode: EnumParam::new("Limiter", LimiterMode::Off),
+
+            limiter_ceiling: FloatParam::new(
+                "Ceiling",
+                util::db_to_gain(-0.3),
+                FloatRange::Skewed {
+                    min: util::db_to_gain(-12.0),
+                    max: util::db_to_gain(0.0),
+                    factor: FloatRange::gain_skew_factor(-12.0, 0.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" dB Ceiling")
+            .with_value_to_string(formatters::v2s_f32_gain_to_db(1))
+            .with_string_to_value(formatters::s2v_f32_gain_to_db()),
+
+            // On by default, some of the feedback presets can get dangerously loud
+            runaway_protection: BoolParam::new("Runaway Protection", true),
+
+            // Keeps the wet roughly level when stack and decay change
+            auto_gain: BoolParam::new("Auto Gain", false),
+
+            // Louder Less gives a longer tail when the input is quiet and a shorter one when it's busy
+            env_direction: EnumParam::new("Env Direction", EnvelopeDirection::LouderLess),
+
+            env_attack: FloatParam::new(
+                "Env Attack",
+                10.0,
+                FloatRange::Skewed {
+                    min: 0.1,
+                    max: 200.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_value_to_string(formatters::v2s_f32_rounded(1))
+            .with_unit(" ms Attack"),
+
+            env_release: FloatParam::new(
+                "Env Release",
+                300.0,
+                FloatRange::Skewed {
+                    min: 10.0,
+                    max: 2000.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_value_to_string(formatters::v2s_f32_rounded(0))
+            .with_unit(" ms Release"),
+
+            env_decay_depth: FloatParam::new(
+                "Env Decay Depth",
+                0.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_smoother(SmoothingStyle::Linear(30.0))
+            .with_unit("% Env Decay")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+
+            env_mix_depth: FloatParam::new(
+                "Env Mix Depth",
+                0.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_smoother(SmoothingStyle::Linear(30.0))
+            .with_unit("% Env Mix")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+
+            env_cutoff_depth: FloatParam::new(
+                "Env Cutoff Depth",
+                0.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_smoother(SmoothingStyle::Linear(30.0))
+            .with_unit("% Env Cutoff")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+
+            // Negative is warmer, positive is brighter
+            tilt: FloatParam::new(
+                "Tilt",
+                0.0,
+                FloatRange::Linear { min: -12.0, max: 12.0 },
+            )
+            .with_smoother(SmoothingStyle::Linear(30.0))
+            .with_value_to_string(formatters::v2s_f32_rounded(1))
+            .with_unit(" dB Tilt"),
+
+            age: FloatParam::new(
+                "Age",
+                0.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_smoother(SmoothingStyle::Linear(30.0))
+            .with_unit("% Age")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+
+            ambi_spread: FloatParam::new(
+                "Ambi Spread",
+                1.0,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            )
+            .with_smoother(SmoothingStyle::Linear(30.0))
+            .with_unit("% Spread")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+
+            // A plain delay on one side of the wet, so it means the same thing at any sample rate
+            haas_mode: EnumParam::new("Haas Mode", HaasMode::Milliseconds),
+
+            haas_time: FloatParam::new(
+                "Haas Time",
+                0.0,
+                FloatRange::Skewed {
+                    min: 0.0,
+                    max: 100.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(30.0))
+            .with_value_to_string(formatters::v2s_f32_rounded(2))
+            .with_unit(" ms Haas"),
+
+            haas_note: EnumParam::new("Haas Note", HaasNote::SixtyFourth),
+
+            haas_invert: BoolParam::new("Haas Invert", false),
+
+            haas_swap: BoolParam::new("Haas Swap Sides", false),
+
+            // The old buffer shift in samples, hidden now that the Haas delay does the same job.
+            // Anything but zero takes over the Haas time and side, presets get moved over in filter_state
+            width_offset: IntParam::new(
+                "Reverb Offset",
+                0,
+                IntRange::Linear { min: -435, max: 435 },
+            )
+            .with_unit(" Offset")
+            .hide(),
+
+            // Same seed, same random width, so bounces null against each other
+            random_seed: IntParam::new(
+                "Random Seed",
+                1,
+                IntRange::Linear { min: 0, max: 9999 },
+            ),
+
+            width_random: FloatParam::new(
+                "Reverb Rand",
+                0.0,
+                FloatRange::Linear { min: 0.0, max: 10.0 },
+            )
+            .with_smoother(SmoothingStyle::Linear(30.0))
+            .with_value_to_string(formatters::v2s_f32_rounded(3))
+            .with_unit(" Rand"),
+
+            reverb_steps: IntParam::new(
+                "Reverb Steps",
+                10,
+                IntRange::Linear {
+                    min: 2,
+                    max: MAX_REVERB_STEPS,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(30.0))
+            .with_unit(" Steps"),
+
+            reverb_step_alg: EnumParam::new("Step Alg",reverb::ReverbType::ExpSwirl),
+
+            reverb_low_cut: FloatParam::new(
+                "Reverb High Pass",
+                30.0,
+                FloatRange::Skewed {
+                    min: MIN_HIGH_PASS_HZ,
+                    max: 2000.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(30.0))
+            .with_value_to_string(formatters::v2s_f32_hz_then_khz(1))
+            .with_string_to_value(formatters::s2v_f32_hz_then_khz())
+            .with_unit(" High Pass")
+            ,
+
+            reverb_high_cut: FloatParam::new(
+                "Reverb Low Pass",
+                18000.0,
+                FloatRange::Skewed {
+                    min: MIN_LOW_PASS_HZ,
+                    max: MAX_LOW_PASS_HZ,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(30.0))
+            .with_value_to_string(formatters::v2s_f32_hz_then_khz(1))
+            .with_string_to_value(formatters::s2v_f32_hz_then_khz())
+            .with_unit(" Low Pass")
+            ,
+
+            // Post is the original behaviour, filtering the finished tail
+            filter_placement: EnumParam::new("Filter Placement", FilterPlacement::PostStack),
+
+            reverb_low_cut_slope: EnumParam::new("Reverb High Pass Slope", FilterSlope::Db12),
+
+            reverb_high_cut_slope: EnumParam::new("Reverb Low Pass Slope", FilterSlope::Db12),
+
+            reverb_low_cut_q: FloatParam::new(
+                "Reverb High Pass Q",
+                BUTTERWORTH_Q,
+                FloatRange::Skewed {
+                    min: 0.5,
+                    max: 10.0,
+                    factor: FloatRange::skew_factor(-1.5),
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(30.0))
+            .with_value_to_string(formatters::v2s_f32_rounded(2))
+            .with_unit(" High Pass Q"),
+
+            reverb_high_cut_q: FloatParam::new(
+                "Reverb Low Pass Q",
+                BUTTERWORTH_Q,
+                FloatRange::Skewed {
+                    min: 0.5,
+                    max: 10.0,
+                    factor: FloatRange::skew_factor(-1.5),
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(30.0))
+            .with_value_to_string(formatters::v2s_f32_rounded(2))
+            .with_unit(" Low Pass Q"),
+
+            eq_low_freq: FloatParam::new(
+                "EQ Low Shelf Freq",
+                120.0,
+                FloatRange::Skewed {
+                    min: 20.0,
+                    max: 1000.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(30.0))
+            .with_value_to_string(formatters::v2s_f32_hz_then_khz(1))
+            .with_string_to_value(formatters::s2v_f32_hz_then_khz())
+            .with_unit(" Low Shelf"),
+
+            eq_low_gain: FloatParam::new(
+                "EQ Low Shelf Gain",
+                0.0,
+                FloatRange::Linear {
+                    min: -18.0,
+                    max: 18.0,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(30.0))
+            .with_value_to_string(formatters::v2s_f32_rounded(1))
+            .with_unit(" dB Low Shelf"),
+
+            eq_low_q: FloatParam::new(
+                "EQ Low Shelf Q",
+                BUTTERWORTH_Q,
+                FloatRange::Skewed {
+                    min: 0.1,
+                    max: 10.0,
+                    factor: FloatRange::skew_factor(-1.5),
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(30.0))
+            .with_value_to_string(formatters::v2s_f32_rounded(2))
+            .with_unit(" Low Shelf Q"),
+
+            eq_mid1_freq: FloatParam::new(
+                "EQ Peak 1 Freq",
+                500.0,
+                FloatRange::Skewed {
+                    min: 60.0,
+                    max: 8000.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(30.0))
+            .with_value_to_string(formatters::v2s_f32_hz_then_khz(1))
+            .with_string_to_value(formatters::s2v_f32_hz_then_khz())
+            .with_unit(" Peak 1"),
+
+            eq_mid1_gain: FloatParam::new(
+                "EQ Peak 1 Gain",
+                0.0,
+                FloatRange::Linear {
+                    min: -18.0,
+                    max: 18.0,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(30.0))
+            .with_value_to_string(formatters::v2s_f32_rounded(1))
+            .with_unit(" dB Peak 1"),
+
+            eq_mid1_q: FloatParam::new(
+                "EQ Peak 1 Q",
+                BUTTERWORTH_Q,
+                FloatRange::Skewed {
+                    min: 0.1,
+                    max: 10.0,
+                    factor: FloatRange::skew_factor(-1.5),
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(30.0))
+            .with_value_to_string(formatters::v2s_f32_rounded(2))
+            .with_unit(" Peak 1 Q"),
+
+            eq_mid2_freq: FloatParam::new(
+                "EQ Peak 2 Freq",
+                3000.0,
+                FloatRange::Skewed {
+                    min: 200.0,
+                    max: 16000.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(30.0))
+            .with_value_to_string(formatters::v2s_f32_hz_then_khz(1))
+            .with_string_to_value(formatters::s2v_f32_hz_then_khz())
+            .with_unit(" Peak 2"),
+
+            eq_mid2_gain: FloatParam::new(
+                "EQ Peak 2 Gain",
+                0.0,
+                FloatRange::Linear {
+                    min: -18.0,
+                    max: 18.0,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(30.0))
+            .with_value_to_string(formatters::v2s_f32_rounded(1))
+            .with_unit(" dB Peak 2"),
+
+            eq_mid2_q: FloatParam::new(
+                "EQ Peak 2 Q",
+                BUTTERWORTH_Q,
+                FloatRange::Skewed {
+                    min: 0.1,
+                    max: 10.0,
+                    factor: FloatRange::skew_factor(-1.5),
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(30.0))
+            .with_value_to_string(formatters::v2s_f32_rounded(2))
+            .with_unit(" Peak 2 Q"),
+
+            eq_high_freq: FloatParam::new(
+                "EQ High Shelf Freq",
+                8000.0,
+                FloatRange::Skewed {
+                    min: 1000.0,
+                    max: 20000.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(30.0))
+            .with_value_to_string(formatters::v2s_f32_hz_then_khz(1))
+            .with_string_to_value(formatters::s2v_f32_hz_then_khz())
+            .with_unit(" High Shelf"),
+
+            eq_high_gain: FloatParam::new(
+                "EQ High Shelf Gain",
+                0.0,
+                FloatRange::Linear {
+                    min: -18.0,
+                    max: 18.0,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(30.0))
+            .with_value_to_string(formatters::v2s_f32_rounded(1))
+            .with_unit(" dB High Shelf"),
+
+            eq_high_q: FloatParam::new(
+                "EQ High Shelf Q",
+                BUTTERWORTH_Q,
+                FloatRange::Skewed {
+                    min: 0.1,
+                    max: 10.0,
+                    factor: FloatRange::skew_factor(-1.5),
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(30.0))
+            .with_value_to_string(formatters::v2s_f32_rounded(2))
+            .with_unit(" High Shelf Q"),
+
+            mono_widen: BoolParam::new("Mono Widen", false),
+
+            reverb_sidechain: FloatParam::new(
+                "Self Sidechain",
+                0.0,
+                FloatRange::Linear {
+                    min: -36.0,
+                    max: 0.0,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(10.0))
+            .with_value_to_string(formatters::v2s_f32_rounded(1))
+            .with_unit("dB Self Sidechain Threshold")
+            ,
+
+            key_source: EnumParam::new("Key Source", KeySource::Internal),
+
+            duck_attack: FloatParam::new(
+                "Duck Attack",
+                10.0,
+                FloatRange::Skewed {
+                    min: 0.1,
+                    max: 200.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_value_to_string(formatters::v2s_f32_rounded(1))
+            .with_unit(" ms Attack"),
+
+            duck_release: FloatParam::new(
+                "Duck Release",
+                250.0,
+                FloatRange::Skewed {
+                    min: 10.0,
+                    max: 2000.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_value_to_string(formatters::v2s_f32_rounded(0))
+            .with_unit(" ms Release"),
+
+            duck_hold: FloatParam::new(
+                "Duck Hold",
+                0.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 500.0,
+                },
+            )
+            .with_value_to_string(formatters::v2s_f32_rounded(0))
+            .with_unit(" ms Hold"),
+
+            duck_ratio: FloatParam::new(
+                "Duck Ratio",
+                4.0,
+                FloatRange::Skewed {
+                    min: 1.0,
+                    max: 20.0,
+                    factor: FloatRange::skew_factor(-1.5),
+                },
+            )
+            .with_value_to_string(formatters::v2s_f32_rounded(1))
+            .with_unit(":1 Ratio"),
+
+            duck_range: FloatParam::new(
+                "Duck Range",
+                12.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 60.0,
+                },
+            )
+            .with_value_to_string(formatters::v2s_f32_rounded(1))
+            .with_unit(" dB Max Duck"),
+
+            duck_knee: FloatParam::new(
+                "Duck Knee",
+                6.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 24.0,
+                },
+            )
+            .with_value_to_string(formatters::v2s_f32_rounded(1))
+            .with_unit(" dB Knee"),
+
+            duck_link: FloatParam::new(
+                "Duck Link",
+                1.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 1.0,
+                },
+            )
+            .with_unit("% Stereo Link")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage()),
+
+            duck_detection: EnumParam::new("Duck Detection", DetectionMode::Peak),
+
+            // Not smoothed since this changes the latency we report
+            duck_lookahead: FloatParam::new(
+                "Duck Lookahead",
+                0.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: MAX_LOOKAHEAD_MS,
+                },
+            )
+            .with_value_to_string(formatters::v2s_f32_rounded(1))
+            .with_unit(" ms Lookahead"),
+
+            // One band ducks the whole tail with the threshold and range above
+            duck_bands: IntParam::new("Duck Bands", 1, IntRange::Linear { min: 1, max: MAX_DUCK_BANDS as i32 })
+            .with_unit(" Duck Bands"),
+
+            duck_low_crossover: FloatParam::new(
+                "Duck Low Crossover",
+                250.0,
+                FloatRange::Skewed {
+                    min: 40.0,
+                    max: 1000.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
             )
-            .with_smoother(SmoothingStyle::Linear(30.0))
-            .with_value_to_string(formatters::v2s_f32_rounded(2))
-            .with_unit(" Width"),
+            .with_value_to_string(formatters::v2s_f32_hz_then_khz(0))
+            .with_unit(" Low Split"),
 
-            // 435 is a quarter note at 138 bpm :)
-            // 428 is a quarter note at 140
-            width_offset: IntParam::new(
-                "Reverb Offset",
-                0,
-                IntRange::Linear { min: -435, max: 435 },
+            duck_high_crossover: FloatParam::new(
+                "Duck High Crossover",
+                4000.0,
+                FloatRange::Skewed {
+                    min: 1000.0,
+                    max: 12000.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
             )
-            .with_smoother(SmoothingStyle::Linear(30.0))
-            .with_unit(" Offset"),
+            .with_value_to_string(formatters::v2s_f32_hz_then_khz(0))
+            .with_unit(" High Split"),
 
-            width_random: FloatParam::new(
-                "Reverb Rand",
+            duck_low_threshold: FloatParam::new(
+                "Duck Low Threshold",
                 0.0,
-                FloatRange::Linear { min: 0.0, max: 10.0 },
+                FloatRange::Linear {
+                    min: -36.0,
+                    max: 0.0,
+                },
             )
-            .with_smoother(SmoothingStyle::Linear(30.0))
-            .with_value_to_string(formatters::v2s_f32_rounded(3))
-            .with_unit(" Rand"),
+            .with_smoother(SmoothingStyle::Linear(10.0))
+            .with_value_to_string(formatters::v2s_f32_rounded(1))
+            .with_unit("dB Low Threshold"),
 
-            reverb_steps: IntParam::new(
-                "Reverb Steps",
-                10,
-                IntRange::Linear {
-                    min: 2,
-                    max: 36,
+            duck_low_depth: FloatParam::new(
+                "Duck Low Depth",
+                24.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 60.0,
                 },
             )
-            .with_smoother(SmoothingStyle::Linear(30.0))
-            .with_unit(" Steps"),
-
-            reverb_step_alg: EnumParam::new("Step Alg",reverb::ReverbType::ExpSwirl),
+            .with_value_to_string(formatters::v2s_f32_rounded(1))
+            .with_unit(" dB Low Depth"),
 
-            reverb_low_cut: FloatParam::new(
-                "Reverb High Pass",
+            duck_mid_threshold: FloatParam::new(
+                "Duck Mid Threshold",
                 0.0,
                 FloatRange::Linear {
-                    min: 0.0,
-                    max: 0.6,
+                    min: -36.0,
+                    max: 0.0,
                 },
             )
-            .with_smoother(SmoothingStyle::Linear(30.0))
-            //.with_value_to_string(formatters::v2s_f32_rounded(4))
-            .with_value_to_string(format_hp_filter(2))
-            .with_unit("Hz High Pass")
-            ,
+            .with_smoother(SmoothingStyle::Linear(10.0))
+            .with_value_to_string(formatters::v2s_f32_rounded(1))
+            .with_unit("dB Mid Threshold"),
 
-            reverb_high_cut: FloatParam::new(
-                "Reverb Low Pass",
-                1.15,
+            duck_mid_depth: FloatParam::new(
+                "Duck Mid Depth",
+                12.0,
                 FloatRange::Linear {
                     min: 0.0,
-                    max: 1.8,
+                    max: 60.0,
                 },
             )
-            .with_smoother(SmoothingStyle::Linear(30.0))
-            //.with_value_to_string(formatters::v2s_f32_rounded(4))
-            .with_value_to_string(format_lp_filter(2))
-            .with_unit("Khz Low Pass")
-            ,
+            .with_value_to_string(formatters::v2s_f32_rounded(1))
+            .with_unit(" dB Mid Depth"),
 
-            reverb_sidechain: FloatParam::new(
-                "Self Sidechain",
+            duck_high_threshold: FloatParam::new(
+                "Duck High Threshold",
                 0.0,
                 FloatRange::Linear {
                     min: -36.0,
@@ -251,8 +1243,18 @@ impl Default for GainParams {
             )
             .with_smoother(SmoothingStyle::Linear(10.0))
             .with_value_to_string(formatters::v2s_f32_rounded(1))
-            .with_unit("dB Self Sidechain Threshold")
-            ,
+            .with_unit("dB High Threshold"),
+
+            duck_high_depth: FloatParam::new(
+                "Duck High Depth",
+                6.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 60.0,
+                },
+            )
+            .with_value_to_string(formatters::v2s_f32_rounded(1))
+            .with_unit(" dB High Depth"),
 
             // Output gain parameter
             output_gain: FloatParam::new(
@@ -282,8 +1284,124 @@ impl Default for GainParams {
             .with_unit("% Wet")
             .with_value_to_string(formatters::v2s_f32_percentage(2))
             .with_string_to_value(formatters::s2v_f32_percentage()),
+
+            // Fully wet with no dry at all, for aux sends
+            send_mode: BoolParam::new("Send Mode", false),
+
+            dry_level: FloatParam::new(
+                "Dry Level",
+                util::db_to_gain(0.0),
+                FloatRange::Skewed {
+                    min: util::db_to_gain(-48.0),
+                    max: util::db_to_gain(6.0),
+                    factor: FloatRange::gain_skew_factor(-48.0, 6.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" dB Dry")
+            .with_value_to_string(formatters::v2s_f32_gain_to_db(1))
+            .with_string_to_value(formatters::s2v_f32_gain_to_db()),
+
+            wet_level: FloatParam::new(
+                "Wet Level",
+                util::db_to_gain(0.0),
+                FloatRange::Skewed {
+                    min: util::db_to_gain(-48.0),
+                    max: util::db_to_gain(6.0),
+                    factor: FloatRange::gain_skew_factor(-48.0, 6.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" dB Wet")
+            .with_value_to_string(formatters::v2s_f32_gain_to_db(1))
+            .with_string_to_value(formatters::s2v_f32_gain_to_db()),
+        }
+    }
+}
+
+impl Gain {
+    // Stacks are processed in series so their tails add up, channels can differ with the detune.
+    // The lookahead and the Haas delay push the whole tail back on top of that
+    fn estimate_tail_samples(&self) -> u32 {
+        let delayed_start = self.lookahead.delay() as u32 + self.haas.delay_samples().ceil() as u32;
+        (0..self.active_stacks)
+            .map(|stack| {
+                let surround_tail = self.surround_arrays.iter().filter_map(|stacks| stacks.get(stack)).map(|reverb| reverb.tail_samples()).max().unwrap_or(0);
+                self.reverb_l_array[stack].tail_samples()
+                    .max(self.reverb_r_array[stack].tail_samples())
+                    .max(surround_tail)
+            })
+            .fold(delayed_start, |total: u32, stack_tail| total.saturating_add(stack_tail))
+    }
+
+    // Silence the stacks and everything after them that keeps state, the dry path is left alone
+    fn clear_wet(&mut self) {
+        for reverb in self.reverb_l_array.iter_mut().chain(self.reverb_r_array.iter_mut()) {
+            reverb.clear();
+        }
+        for reverb in self.surround_arrays.iter_mut().flatten() {
+            reverb.clear();
+        }
+        self.filter_lowpass.reset();
+        self.filter_highpass.reset();
+        for (lowpass, highpass) in self.surround_filters.iter_mut() {
+            lowpass.reset();
+            highpass.reset();
+        }
+        for (lowpass, highpass) in self.ambi_filters.iter_mut() {
+            lowpass.reset();
+            highpass.reset();
+        }
+        for eq in std::iter::once(&mut self.wet_eq).chain(self.surround_eq.iter_mut()).chain(self.ambi_eq.iter_mut()) {
+            eq.reset();
+        }
+        for tilt_eq in std::iter::once(&mut self.wet_tilt).chain(self.surround_tilt.iter_mut()).chain(self.ambi_tilt.iter_mut()) {
+            tilt_eq.reset();
+        }
+        self.surround_dc.iter_mut().chain(self.ambi_dc.iter_mut()).for_each(|dc| *dc = (0.0, 0.0));
+        self.prev_processed_in_l = 0.0;
+        self.prev_processed_in_r = 0.0;
+        self.prev_processed_out_l = 0.0;
+        self.prev_processed_out_r = 0.0;
+        self.prev_rand_offset = 0.0;
+        self.haas.reset();
+        self.envelope.reset();
+        self.ducking.reset();
+    }
+
+    // Wet level relative to one stack at the reference decay. Every stack feeds on the input plus all the
+    // stacks before it, so the energy compounds with the stack count. Steps and delay only stretch the loop
+    fn auto_gain(stacks: usize, left_decay: f32, right_decay: f32) -> f32 {
+        let energy = |decay: f32| (stacks as f32 * Reverb::energy_gain(decay).ln_1p()).exp_m1();
+        let wet_energy = 0.5 * (energy(left_decay) + energy(right_decay));
+        let reference = Reverb::energy_gain(AUTO_GAIN_REFERENCE_DECAY);
+        (reference / wet_energy.max(f32::MIN_POSITIVE)).sqrt()
+            .clamp(util::db_to_gain(AUTO_GAIN_MIN_DB), util::db_to_gain(AUTO_GAIN_MAX_DB))
+    }
+
+    // Fan the stacks from the front round to the back, alternating above and below the horizon
+    fn update_ambi_directions(&mut self) {
+        let stacks = self.active_stacks.max(1) as f32;
+        for (stack, direction) in self.ambi_directions.iter_mut().enumerate() {
+            let azimuth = (stack as f32 + 0.5) / stacks * f32::consts::PI;
+            let elevation = if stack % 2 == 0 { AMBIX_ELEVATION } else { -AMBIX_ELEVATION };
+            *direction = [
+                elevation.cos() * azimuth.cos(),
+                elevation.cos() * azimuth.sin(),
+                elevation.sin(),
+            ];
         }
     }
+
+    // Ducking lookahead in samples at the current sample rate
+    fn lookahead_samples(&self) -> usize {
+        (self.params.duck_lookahead.value() * 0.001 * self.sample_rate).round() as usize
+    }
+
+    // The LFE channel of 5.1 and 7.1 layouts stays dry
+    fn is_lfe(&self, channel: usize) -> bool {
+        self.num_output_channels >= 6 && channel == LFE_CHANNEL
+    }
 }
 
 impl Plugin for Gain {
@@ -294,10 +1412,17 @@ impl Plugin for Gain {
 
     const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
-    // This looks like it's flexible for running the plugin in mono or stereo
+    // Stereo, mono into a stereo tail, plain mono, then quad, 5.1, 7.1 and AmbiX
+    // Every layout also gets a stereo sidechain input for keying the ducking
     const AUDIO_IO_LAYOUTS: &'static [AudioIOLayout] = &[
-        AudioIOLayout {main_input_channels: NonZeroU32::new(2), main_output_channels: NonZeroU32::new(2), ..AudioIOLayout::const_default()},
-        AudioIOLayout {main_input_channels: NonZeroU32::new(1), main_output_channels: NonZeroU32::new(1), ..AudioIOLayout::const_default()},
+        AudioIOLayout {main_input_channels: NonZeroU32::new(2), main_output_channels: NonZeroU32::new(2), aux_input_ports: SIDECHAIN_PORTS, names: PortNames {aux_inputs: SIDECHAIN_NAMES, ..PortNames::const_default()}, ..AudioIOLayout::const_default()},
+        AudioIOLayout {main_input_channels: NonZeroU32::new(1), main_output_channels: NonZeroU32::new(2), aux_input_ports: SIDECHAIN_PORTS, names: PortNames {aux_inputs: SIDECHAIN_NAMES, ..PortNames::const_default()}, ..AudioIOLayout::const_default()},
+        AudioIOLayout {main_input_channels: NonZeroU32::new(1), main_output_channels: NonZeroU32::new(1), aux_input_ports: SIDECHAIN_PORTS, names: PortNames {aux_inputs: SIDECHAIN_NAMES, ..PortNames::const_default()}, ..AudioIOLayout::const_default()},
+        AudioIOLayout {main_input_channels: NonZeroU32::new(4), main_output_channels: NonZeroU32::new(4), aux_input_ports: SIDECHAIN_PORTS, names: PortNames {layout: Some("Quad"), aux_inputs: SIDECHAIN_NAMES, ..PortNames::const_default()}, ..AudioIOLayout::const_default()},
+        AudioIOLayout {main_input_channels: NonZeroU32::new(6), main_output_channels: NonZeroU32::new(6), aux_input_ports: SIDECHAIN_PORTS, names: PortNames {layout: Some("5.1"), aux_inputs: SIDECHAIN_NAMES, ..PortNames::const_default()}, ..AudioIOLayout::const_default()},
+        AudioIOLayout {main_input_channels: NonZeroU32::new(8), main_output_channels: NonZeroU32::new(8), aux_input_ports: SIDECHAIN_PORTS, names: PortNames {layout: Some("7.1"), aux_inputs: SIDECHAIN_NAMES, ..PortNames::const_default()}, ..AudioIOLayout::const_default()},
+        AudioIOLayout {main_input_channels: NonZeroU32::new(1), main_output_channels: NonZeroU32::new(4), aux_input_ports: SIDECHAIN_PORTS, names: PortNames {layout: Some(AMBIX_LAYOUT), aux_inputs: SIDECHAIN_NAMES, ..PortNames::const_default()}, ..AudioIOLayout::const_default()},
+        AudioIOLayout {main_input_channels: NonZeroU32::new(2), main_output_channels: NonZeroU32::new(4), aux_input_ports: SIDECHAIN_PORTS, names: PortNames {layout: Some(AMBIX_LAYOUT), aux_inputs: SIDECHAIN_NAMES, ..PortNames::const_default()}, ..AudioIOLayout::const_default()},
     ];
 
     const SAMPLE_ACCURATE_AUTOMATION: bool = true;
@@ -311,12 +1436,15 @@ impl Plugin for Gain {
 
     fn editor(&mut self, _async_executor: AsyncExecutor<Self>) -> Option<Box<dyn Editor>> {
         let params = self.params.clone();
+        let meters = self.meters.clone();
+        let ambisonic_active = self.ambisonic_active.clone();
+        let editor_sample_rate = self.editor_sample_rate.clone();
 
         create_egui_editor(
             self.params.editor_state.clone(),
-            (),
+            EditorTab::Reverb,
             |_, _| {},
-            move |egui_ctx, setter, _state| {
+            move |egui_ctx, setter, tab| {
                 egui::CentralPanel::default()
                     .show(egui_ctx, |ui| {
                         // Change colors - there's probably a better way to do this
@@ -361,94 +1489,244 @@ impl Plugin for Gain {
                             // Spacing :)
                             ui.horizontal(|ui| {
                                 ui.label(RichText::new("    Canopy Reverb").font(FontId::monospace(14.0)).color(A_KNOB_OUTSIDE_COLOR)).on_hover_text("by Ardura!");
+                                ui.add_space(16.0);
+                                ui.selectable_value(tab, EditorTab::Reverb, "Reverb");
+                                ui.selectable_value(tab, EditorTab::Ducking, "Ducking");
+                                ui.selectable_value(tab, EditorTab::Bands, "Bands");
+                                ui.selectable_value(tab, EditorTab::Tone, "Tone");
+                                ui.selectable_value(tab, EditorTab::Stereo, "Stereo");
+                                ui.selectable_value(tab, EditorTab::Drive, "Drive");
+                                ui.selectable_value(tab, EditorTab::Envelope, "Envelope");
                             });
                             ui.separator();
                             let knob_size = 42.0;
 
-                            ui.horizontal(|ui| {
-                                let mut delay_knob = ui_knob::ArcKnob::for_param(&params.reverb_delay, setter, knob_size);
-                                delay_knob.preset_style(ui_knob::KnobStyle::LargeMedium);
-                                delay_knob.set_fill_color(A_KNOB_INSIDE_COLOR);
-                                delay_knob.set_line_color(A_KNOB_OUTSIDE_COLOR);
-                                ui.add(delay_knob);
-
-                                let mut stack_knob = ui_knob::ArcKnob::for_param(&params.reverb_stack, setter, knob_size);
-                                stack_knob.preset_style(ui_knob::KnobStyle::LargeMedium);
-                                stack_knob.set_fill_color(A_KNOB_INSIDE_COLOR);
-                                stack_knob.set_line_color(A_KNOB_OUTSIDE_COLOR);
-                                ui.add(stack_knob);
-
-                                let mut alg_knob = ui_knob::ArcKnob::for_param(&params.reverb_step_alg, setter, knob_size);
-                                alg_knob.preset_style(ui_knob::KnobStyle::SmallTogether);
-                                alg_knob.set_fill_color(A_KNOB_OUTSIDE_COLOR2);
-                                alg_knob.set_line_color(A_KNOB_OUTSIDE_COLOR);
-                                alg_knob.set_text_size(14.0);
-                                ui.add(alg_knob);
-
-                                let mut decay_knob = ui_knob::ArcKnob::for_param(&params.reverb_decay, setter, knob_size);
-                                decay_knob.preset_style(ui_knob::KnobStyle::LargeMedium);
-                                decay_knob.set_fill_color(A_KNOB_INSIDE_COLOR);
-                                decay_knob.set_line_color(A_KNOB_OUTSIDE_COLOR);
-                                ui.add(decay_knob);
-                            });
-
-                            ui.horizontal(|ui| {
-                                let mut step_knob = ui_knob::ArcKnob::for_param(&params.reverb_steps, setter, knob_size);
-                                step_knob.preset_style(ui_knob::KnobStyle::LargeMedium);
-                                step_knob.set_fill_color(A_KNOB_INSIDE_COLOR);
-                                step_knob.set_line_color(A_KNOB_OUTSIDE_COLOR);
-                                ui.add(step_knob);
-
-                                let mut width_knob = ui_knob::ArcKnob::for_param(&params.reverb_width, setter, knob_size);
-                                width_knob.preset_style(ui_knob::KnobStyle::LargeMedium);
-                                width_knob.set_fill_color(A_KNOB_INSIDE_COLOR);
-                                width_knob.set_line_color(A_KNOB_OUTSIDE_COLOR);
-                                ui.add(width_knob);
-
-                                let mut width_offset = ui_knob::ArcKnob::for_param(&params.width_offset, setter, knob_size);
-                                width_offset.preset_style(ui_knob::KnobStyle::LargeMedium);
-                                width_offset.set_fill_color(A_KNOB_INSIDE_COLOR);
-                                width_offset.set_line_color(A_KNOB_OUTSIDE_COLOR);
-                                ui.add(width_offset);
-
-                                let mut width_random = ui_knob::ArcKnob::for_param(&params.width_random, setter, knob_size);
-                                width_random.preset_style(ui_knob::KnobStyle::LargeMedium);
-                                width_random.set_fill_color(A_KNOB_INSIDE_COLOR);
-                                width_random.set_line_color(A_KNOB_OUTSIDE_COLOR);
-                                ui.add(width_random);
-                            });
+                            match *tab {
+                                EditorTab::Reverb => {
+                                    ui.horizontal(|ui| {
+                                        let mut delay_knob = ui_knob::ArcKnob::for_param(&params.reverb_delay, setter, knob_size);
+                                        delay_knob.preset_style(ui_knob::KnobStyle::LargeMedium);
+                                        delay_knob.set_fill_color(A_KNOB_INSIDE_COLOR);
+                                        delay_knob.set_line_color(A_KNOB_OUTSIDE_COLOR);
+                                        ui.add(delay_knob);
+
+                                        let mut stack_knob = ui_knob::ArcKnob::for_param(&params.reverb_stack, setter, knob_size);
+                                        stack_knob.preset_style(ui_knob::KnobStyle::LargeMedium);
+                                        stack_knob.set_fill_color(A_KNOB_INSIDE_COLOR);
+                                        stack_knob.set_line_color(A_KNOB_OUTSIDE_COLOR);
+                                        ui.add(stack_knob);
+
+                                        let mut alg_knob = ui_knob::ArcKnob::for_param(&params.reverb_step_alg, setter, knob_size);
+                                        alg_knob.preset_style(ui_knob::KnobStyle::SmallTogether);
+                                        alg_knob.set_fill_color(A_KNOB_OUTSIDE_COLOR2);
+                                        alg_knob.set_line_color(A_KNOB_OUTSIDE_COLOR);
+                                        alg_knob.set_text_size(14.0);
+                                        ui.add(alg_knob);
+
+                                        let mut decay_knob = ui_knob::ArcKnob::for_param(&params.reverb_decay, setter, knob_size);
+                                        decay_knob.preset_style(ui_knob::KnobStyle::LargeMedium);
+                                        decay_knob.set_fill_color(A_KNOB_INSIDE_COLOR);
+                                        decay_knob.set_line_color(A_KNOB_OUTSIDE_COLOR);
+                                        ui.add(decay_knob);
+
+                                        let mut tilt_knob = ui_knob::ArcKnob::for_param(&params.tilt, setter, knob_size);
+                                        tilt_knob.preset_style(ui_knob::KnobStyle::LargeMedium);
+                                        tilt_knob.set_fill_color(A_KNOB_OUTSIDE_COLOR2);
+                                        tilt_knob.set_line_color(A_KNOB_OUTSIDE_COLOR);
+                                        ui.add(tilt_knob);
+                                    });
 
-                            ui.separator();
+                                    ui.horizontal(|ui| {
+                                        let mut step_knob = ui_knob::ArcKnob::for_param(&params.reverb_steps, setter, knob_size);
+                                        step_knob.preset_style(ui_knob::KnobStyle::LargeMedium);
+                                        step_knob.set_fill_color(A_KNOB_INSIDE_COLOR);
+                                        step_knob.set_line_color(A_KNOB_OUTSIDE_COLOR);
+                                        ui.add(step_knob);
+
+                                        let mut width_knob = ui_knob::ArcKnob::for_param(&params.reverb_width, setter, knob_size);
+                                        width_knob.preset_style(ui_knob::KnobStyle::LargeMedium);
+                                        width_knob.set_fill_color(A_KNOB_INSIDE_COLOR);
+                                        width_knob.set_line_color(A_KNOB_OUTSIDE_COLOR);
+                                        ui.add(width_knob);
+
+                                        let mut haas_knob = ui_knob::ArcKnob::for_param(&params.haas_time, setter, knob_size);
+                                        haas_knob.preset_style(ui_knob::KnobStyle::LargeMedium);
+                                        haas_knob.set_fill_color(A_KNOB_INSIDE_COLOR);
+                                        haas_knob.set_line_color(A_KNOB_OUTSIDE_COLOR);
+                                        ui.add(haas_knob);
+
+                                        let mut width_random = ui_knob::ArcKnob::for_param(&params.width_random, setter, knob_size);
+                                        width_random.preset_style(ui_knob::KnobStyle::LargeMedium);
+                                        width_random.set_fill_color(A_KNOB_INSIDE_COLOR);
+                                        width_random.set_line_color(A_KNOB_OUTSIDE_COLOR);
+                                        ui.add(width_random);
+
+                                        let mut age_knob = ui_knob::ArcKnob::for_param(&params.age, setter, knob_size);
+                                        age_knob.preset_style(ui_knob::KnobStyle::LargeMedium);
+                                        age_knob.set_fill_color(A_KNOB_OUTSIDE_COLOR2);
+                                        age_knob.set_line_color(A_KNOB_OUTSIDE_COLOR);
+                                        ui.add(age_knob);
+                                    });
 
-                            let spacer_size = 8.0;
-                            ui.horizontal(|ui| {
-                                ui.add_space(spacer_size);
-                                ui.vertical(|ui| {
-                                    ui.add(OtherParamSlider::for_param(&params.reverb_high_cut, setter).with_width(300.0).set_reversed(true));
-                                    ui.add(ParamSlider::for_param(&params.reverb_low_cut, setter).with_width(300.0));
+                                    ui.separator();
 
+                                    let spacer_size = 8.0;
+                                    ui.horizontal(|ui| {
+                                        ui.add_space(spacer_size);
+                                        ui.vertical(|ui| {
+                                            ui.add(OtherParamSlider::for_param(&params.reverb_high_cut, setter).with_width(300.0).set_reversed(true));
+                                            ui.add(ParamSlider::for_param(&params.reverb_low_cut, setter).with_width(300.0));
+
+                                            ui.horizontal(|ui| {
+                                                ui.add(ParamSlider::for_param(&params.reverb_lock, setter).with_width(16.0));
+                                                ui.add_space(8.0);
+                                                ui.add(ParamSlider::for_param(&params.reverb_sidechain, setter).with_width(100.0));
+                                                ui.add_space(8.0);
+                                                ui.add(ParamSlider::for_param(&params.mono_widen, setter).with_width(32.0));
+                                                ui.add_space(8.0);
+                                                ui.add_enabled(ambisonic_active.load(Ordering::Relaxed), ParamSlider::for_param(&params.ambi_spread, setter).with_width(48.0));
+                                            });
+
+                                            ui.horizontal(|ui| {
+                                                ui.vertical(|ui| {
+                                                    ui.add_space(spacer_size);
+                                                    ui.add(ParamSlider::for_param(&params.send_mode, setter).with_width(knob_size*2.0));
+                                                    ui.add(ParamSlider::for_param(&params.dry_level, setter).with_width(knob_size*2.0));
+                                                    ui.add(ParamSlider::for_param(&params.wet_level, setter).with_width(knob_size*2.0));
+                                                });
+                                                ui.add_space(spacer_size);
+                                                let mut dry_wet_knob = ui_knob::ArcKnob::for_param(&params.dry_wet, setter, knob_size);
+                                                dry_wet_knob.preset_style(ui_knob::KnobStyle::LargeMedium);
+                                                dry_wet_knob.set_fill_color(A_KNOB_OUTSIDE_COLOR2);
+                                                dry_wet_knob.set_line_color(A_KNOB_OUTSIDE_COLOR);
+                                                ui.add(dry_wet_knob);
+
+                                                let mut output_knob = ui_knob::ArcKnob::for_param(&params.output_gain, setter, knob_size);
+                                                output_knob.preset_style(ui_knob::KnobStyle::LargeMedium);
+                                                output_knob.set_fill_color(A_KNOB_OUTSIDE_COLOR2);
+                                                output_knob.set_line_color(A_KNOB_OUTSIDE_COLOR);
+                                                ui.add(output_knob);
+                                            });
+                                        });
+                                        ui.add_space(spacer_size);
+                                        meters::meter_panel(ui, &meters, 128.0, A_KNOB_INSIDE_COLOR);
+                                    });
+                                }
+                                EditorTab::Ducking => {
                                     ui.horizontal(|ui| {
-                                        ui.add(ParamSlider::for_param(&params.reverb_lock, setter).with_width(16.0));
                                         ui.add_space(8.0);
-                                        ui.add(ParamSlider::for_param(&params.reverb_sidechain, setter).with_width(100.0));
+                                        ui.vertical(|ui| {
+                                            ui.add(ParamSlider::for_param(&params.reverb_sidechain, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.key_source, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.duck_attack, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.duck_hold, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.duck_release, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.duck_ratio, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.duck_range, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.duck_knee, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.duck_link, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.duck_detection, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.duck_lookahead, setter).with_width(300.0));
+                                        });
+                                        ui.add_space(8.0);
+                                        meters::meter_panel(ui, &meters, 128.0, A_KNOB_INSIDE_COLOR);
                                     });
-
+                                }
+                                EditorTab::Bands => {
                                     ui.horizontal(|ui| {
-                                        ui.add_space(knob_size*2.0 + spacer_size*2.0);
-                                        let mut dry_wet_knob = ui_knob::ArcKnob::for_param(&params.dry_wet, setter, knob_size);
-                                        dry_wet_knob.preset_style(ui_knob::KnobStyle::LargeMedium);
-                                        dry_wet_knob.set_fill_color(A_KNOB_OUTSIDE_COLOR2);
-                                        dry_wet_knob.set_line_color(A_KNOB_OUTSIDE_COLOR);
-                                        ui.add(dry_wet_knob);
-
-                                        let mut output_knob = ui_knob::ArcKnob::for_param(&params.output_gain, setter, knob_size);
-                                        output_knob.preset_style(ui_knob::KnobStyle::LargeMedium);
-                                        output_knob.set_fill_color(A_KNOB_OUTSIDE_COLOR2);
-                                        output_knob.set_line_color(A_KNOB_OUTSIDE_COLOR);
-                                        ui.add(output_knob);
+                                        ui.add_space(8.0);
+                                        ui.vertical(|ui| {
+                                            ui.add(ParamSlider::for_param(&params.duck_bands, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.duck_low_crossover, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.duck_high_crossover, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.duck_low_threshold, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.duck_low_depth, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.duck_mid_threshold, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.duck_mid_depth, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.duck_high_threshold, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.duck_high_depth, setter).with_width(300.0));
+                                        });
                                     });
-                                });
-                            });
+                                }
+                                EditorTab::Tone => {
+                                    ui.horizontal(|ui| {
+                                        ui.add_space(8.0);
+                                        ui.vertical(|ui| {
+                                            ui.add(ParamSlider::for_param(&params.filter_placement, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.reverb_low_cut, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.reverb_low_cut_slope, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.reverb_low_cut_q, setter).with_width(300.0));
+                                            ui.add(OtherParamSlider::for_param(&params.reverb_high_cut, setter).with_width(300.0).set_reversed(true));
+                                            ui.add(ParamSlider::for_param(&params.reverb_high_cut_slope, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.reverb_high_cut_q, setter).with_width(300.0));
+                                            ui.add_space(4.0);
+
+                                            let mut eq_curve = ui_eq::EqCurve::for_params(
+                                                [
+                                                    EqBandParams { frequency: &params.eq_low_freq, gain: &params.eq_low_gain, q: &params.eq_low_q },
+                                                    EqBandParams { frequency: &params.eq_mid1_freq, gain: &params.eq_mid1_gain, q: &params.eq_mid1_q },
+                                                    EqBandParams { frequency: &params.eq_mid2_freq, gain: &params.eq_mid2_gain, q: &params.eq_mid2_q },
+                                                    EqBandParams { frequency: &params.eq_high_freq, gain: &params.eq_high_gain, q: &params.eq_high_q },
+                                                ],
+                                                setter,
+                                                egui::vec2(WIDTH as f32 - 24.0, 180.0),
+                                            );
+                                            eq_curve.set_line_color(A_KNOB_OUTSIDE_COLOR);
+                                            eq_curve.set_handle_color(A_KNOB_INSIDE_COLOR);
+                                            eq_curve.set_sample_rate(editor_sample_rate.load(Ordering::Relaxed));
+                                            ui.add(eq_curve);
+                                        });
+                                    });
+                                }
+                                EditorTab::Stereo => {
+                                    ui.horizontal(|ui| {
+                                        ui.add_space(8.0);
+                                        ui.vertical(|ui| {
+                                            ui.add(ParamSlider::for_param(&params.mid_side, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.mid_decay, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.side_decay, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.mid_level, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.side_level, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.random_seed, setter).with_width(300.0));
+                                            ui.add_space(8.0);
+                                            ui.add(ParamSlider::for_param(&params.haas_mode, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.haas_time, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.haas_note, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.haas_invert, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.haas_swap, setter).with_width(300.0));
+                                        });
+                                    });
+                                }
+                                EditorTab::Drive => {
+                                    ui.horizontal(|ui| {
+                                        ui.add_space(8.0);
+                                        ui.vertical(|ui| {
+                                            ui.add(ParamSlider::for_param(&params.saturation_type, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.saturation_placement, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.saturation_drive, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.saturation_character, setter).with_width(300.0));
+                                            ui.add_space(8.0);
+                                            ui.add(ParamSlider::for_param(&params.limiter_mode, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.limiter_ceiling, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.runaway_protection, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.auto_gain, setter).with_width(300.0));
+                                        });
+                                    });
+                                }
+                                EditorTab::Envelope => {
+                                    ui.horizontal(|ui| {
+                                        ui.add_space(8.0);
+                                        ui.vertical(|ui| {
+                                            ui.add(ParamSlider::for_param(&params.env_direction, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.env_attack, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.env_release, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.env_decay_depth, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.env_mix_depth, setter).with_width(300.0));
+                                            ui.add(ParamSlider::for_param(&params.env_cutoff_depth, setter).with_width(300.0));
+                                        });
+                                    });
+                                }
+                            }
                         });
                     });
                 }
@@ -457,88 +1735,290 @@ impl Plugin for Gain {
 
     fn initialize(
         &mut self,
-        _audio_io_layout: &AudioIOLayout,
-        _buffer_config: &BufferConfig,
-        _context: &mut impl InitContext<Self>,
+        audio_io_layout: &AudioIOLayout,
+        buffer_config: &BufferConfig,
+        context: &mut impl InitContext<Self>,
     ) -> bool {
+        self.sample_rate = buffer_config.sample_rate;
+        self.editor_sample_rate.store(self.sample_rate, Ordering::Relaxed);
+        self.num_input_channels = audio_io_layout.main_input_channels.map(NonZeroU32::get).unwrap_or(0) as usize;
+        self.num_output_channels = audio_io_layout.main_output_channels.map(NonZeroU32::get).unwrap_or(0) as usize;
+        self.ambisonic_output = audio_io_layout.names.layout == Some(AMBIX_LAYOUT);
+        self.ambisonic_active.store(self.ambisonic_output, Ordering::Relaxed);
+
+        // Allocate every stack for the longest delay now so process() never has to
+        let max_buffer = Reverb::max_buffer_size(MAX_REVERB_DELAY, MAX_REVERB_STEPS);
+        for reverb in self.reverb_l_array.iter_mut() {
+            reverb.reserve(max_buffer, MAX_REVERB_STEPS as usize);
+        }
+        // Mono widen stretches the right side past the longest delay
+        let max_widened_buffer = Reverb::max_buffer_size((MAX_REVERB_DELAY as f32 * MONO_WIDEN_DETUNE).ceil() as i32, MAX_REVERB_STEPS);
+        for reverb in self.reverb_r_array.iter_mut() {
+            reverb.reserve(max_widened_buffer, MAX_REVERB_STEPS as usize);
+        }
+
+        // Every channel past the front pair gets its own set of stacks and filters, AmbiX encodes the front stacks instead
+        let surround_channels = if self.ambisonic_output { 0 } else { self.num_output_channels.saturating_sub(2).min(MAX_SURROUND_CHANNELS) };
+        // The LFE gets no stacks at all since it never carries any reverb
+        let max_surround_buffer = Reverb::max_buffer_size((MAX_REVERB_DELAY as f32 * MAX_SURROUND_DETUNE).ceil() as i32, MAX_REVERB_STEPS);
+        self.surround_arrays = (0..surround_channels).map(|index| {
+            if self.is_lfe(index + 2) {
+                return Vec::new();
+            }
+            (0..MAX_REVERB_STACK).map(|_| {
+                let mut reverb = reverb::Reverb::new(VecDeque::from(vec![0; 400]),0.6,400);
+                reverb.reserve(max_surround_buffer, MAX_REVERB_STEPS as usize);
+                reverb
+            }).collect()
+        }).collect();
+        self.surround_filters = (0..surround_channels.div_ceil(2))
+            .map(|_| (
+                StereoSlopeFilter::new(true, 18000.0, FilterSlope::Db12, BUTTERWORTH_Q, self.sample_rate),
+                StereoSlopeFilter::new(false, 30.0, FilterSlope::Db12, BUTTERWORTH_Q, self.sample_rate),
+            ))
+            .collect();
+        self.surround_dc = vec![(0.0, 0.0); surround_channels];
+        self.surround_eq = vec![FourBandEq::new([(1000.0, 0.0, BUTTERWORTH_Q); EQ_BANDS], self.sample_rate); surround_channels.div_ceil(2)];
+        self.surround_tilt = vec![TiltEq::new(0.0, self.sample_rate); surround_channels.div_ceil(2)];
+        // The EQ and tilt pick up their settings again on the first sample
+        self.prev_eq_settings = [(0.0, 0.0, 0.0); EQ_BANDS];
+        self.prev_tilt = f32::MAX;
+        // Force the stacks and filters to pick up the current parameters, the new surround stacks start without feedback filters
+        self.active_stacks = 0;
+        self.prev_filter_placement = FilterPlacement::PostStack;
+        for reverb in self.reverb_l_array.iter_mut().chain(self.reverb_r_array.iter_mut()) {
+            reverb.set_filter_feedback(false);
+        }
+
+        // Ducking timing depends on the real sample rate
+        let sc_threshold = self.params.reverb_sidechain.value();
+        let duck_attack = self.params.duck_attack.value();
+        let duck_release = self.params.duck_release.value();
+        let duck_hold = self.params.duck_hold.value();
+        self.ducking = MultibandDucking::new(sc_threshold, duck_attack, duck_release, duck_hold, self.sample_rate);
+        self.meter_decay_weight = Meters::decay_weight(self.sample_rate);
+        self.limiter = OutputLimiter::new(self.sample_rate);
+        self.haas = StereoHaas::new(self.sample_rate);
+        self.envelope = EnvelopeFollower::new(self.params.env_attack.value(), self.params.env_release.value(), self.sample_rate);
+        self.auto_gain_weight = (-1.0 / (AUTO_GAIN_SMOOTH_MS * 0.001 * self.sample_rate)).exp();
+        self.runaway_guard = RunawayGuard::new(self.sample_rate);
+        // Start already in the right mode rather than fading into it
+        self.send_fade = if self.params.send_mode.value() { 1.0 } else { 0.0 };
+        self.prev_duck_attack = duck_attack;
+        self.prev_duck_release = duck_release;
+        self.prev_duck_hold = duck_hold;
+        // Room for the longest lookahead at this sample rate
+        let max_lookahead = (MAX_LOOKAHEAD_MS * 0.001 * self.sample_rate).ceil() as usize;
+        self.lookahead = Lookahead::new(LOOKAHEAD_FEED_SLOT + 1, max_lookahead);
+        self.lookahead.set_delay(self.lookahead_samples());
+        context.set_latency_samples(self.lookahead.delay() as u32);
+
+        // Thresholds, curves, detection and crossovers get picked up again on the first sample
+        self.prev_duck_thresholds = [f32::MAX; MAX_DUCK_BANDS];
+        self.prev_duck_curves = [(0.0, 0.0, 0.0); MAX_DUCK_BANDS];
+        self.prev_duck_detection = (DetectionMode::Peak, -1.0);
+        self.prev_duck_crossovers = (0.0, 0.0);
+
         true
     }
 
     fn process(
         &mut self,
         buffer: &mut Buffer,
-        _aux: &mut AuxiliaryBuffers,
-        _context: &mut impl ProcessContext<Self>,
+        aux: &mut AuxiliaryBuffers,
+        context: &mut impl ProcessContext<Self>,
     ) -> ProcessStatus {
-        for mut channel_samples in buffer.iter_samples() {
+        // Lookahead is latency so the host needs to know when it changes
+        let lookahead_samples = self.lookahead_samples();
+        if lookahead_samples != self.lookahead.delay() {
+            self.lookahead.set_delay(lookahead_samples);
+            context.set_latency_samples(self.lookahead.delay() as u32);
+        }
+
+        // A new seed starts the random width sequence over
+        let seed = self.params.random_seed.value();
+        if seed != self.prev_seed {
+            self.rng = XorShift32::new(seed as u32);
+            self.prev_seed = seed;
+        }
+
+        // Note values follow the host tempo, hosts that don't report one get 120
+        let tempo = context.transport().tempo.unwrap_or(120.0) as f32;
+        let haas_note_samples = self.params.haas_note.value().beats() * 60.0 / tempo * self.sample_rate;
+
+        // Hosts that don't connect the sidechain just give us silence here
+        let sidechain = aux.inputs.first().map(|sidechain| sidechain.as_slice_immutable());
+
+        // Loudest moments of this buffer for the editor meters
+        let num_samples = buffer.samples();
+        let mut input_peak: f32 = 0.0;
+        let mut wet_peak: f32 = 0.0;
+        let mut output_peak: f32 = 0.0;
+        let mut reduction_peak: f32 = 0.0;
+
+        for (sample_index, mut channel_samples) in buffer.iter_samples().enumerate() {
             let mut processed_sample_l: f32;
             let mut processed_sample_r: f32;
 
-            let reverb_stack: i32 = self.params.reverb_stack.smoothed.next();
+            let reverb_stack: usize = (self.params.reverb_stack.smoothed.next().max(1) as usize).min(MAX_REVERB_STACK);
             let reverb_lock: i32 = self.params.reverb_lock.value();
             let reverb_delay: i32 = self.params.reverb_delay.smoothed.next();
             let reverb_decay: f32 = self.params.reverb_decay.smoothed.next();
-            let width_offset: i32 = self.params.width_offset.smoothed.next();
+            // Keep the smoother moving in Note mode too, otherwise switching back glides from a stale time
+            let haas_time: f32 = self.params.haas_time.smoothed.next();
+            let haas_samples: f32 = match self.params.haas_mode.value() {
+                HaasMode::Milliseconds => haas_time * 0.001 * self.sample_rate,
+                HaasMode::Note => haas_note_samples,
+            };
+            let mid_side: (bool, f32, f32) = (self.params.mid_side.value(), self.params.mid_decay.smoothed.next(), self.params.side_decay.smoothed.next());
+            let mid_level: f32 = self.params.mid_level.smoothed.next();
+            let side_level: f32 = self.params.side_level.smoothed.next();
             let width_random: f32 = self.params.width_random.smoothed.next();
             let reverb_steps: i32 = self.params.reverb_steps.smoothed.next();
             let reverb_low_cut: f32 = self.params.reverb_low_cut.smoothed.next();
             let reverb_high_cut: f32 = self.params.reverb_high_cut.smoothed.next();
+            let filter_shape: (FilterSlope, f32, FilterSlope, f32) = (
+                self.params.reverb_low_cut_slope.value(),
+                self.params.reverb_low_cut_q.smoothed.next(),
+                self.params.reverb_high_cut_slope.value(),
+                self.params.reverb_high_cut_q.smoothed.next(),
+            );
+            let tilt: f32 = self.params.tilt.smoothed.next();
+            let age: f32 = self.params.age.smoothed.next();
+            let saturation: (SaturationType, f32, f32, SaturationPlacement) = (
+                self.params.saturation_type.value(),
+                self.params.saturation_drive.smoothed.next(),
+                self.params.saturation_character.smoothed.next(),
+                self.params.saturation_placement.value(),
+            );
+            let filter_placement: FilterPlacement = self.params.filter_placement.value();
+            let post_filters: bool = filter_placement == FilterPlacement::PostStack;
+            let eq_settings: [(f32, f32, f32); EQ_BANDS] = [
+                (self.params.eq_low_freq.smoothed.next(), self.params.eq_low_gain.smoothed.next(), self.params.eq_low_q.smoothed.next()),
+                (self.params.eq_mid1_freq.smoothed.next(), self.params.eq_mid1_gain.smoothed.next(), self.params.eq_mid1_q.smoothed.next()),
+                (self.params.eq_mid2_freq.smoothed.next(), self.params.eq_mid2_gain.smoothed.next(), self.params.eq_mid2_q.smoothed.next()),
+                (self.params.eq_high_freq.smoothed.next(), self.params.eq_high_gain.smoothed.next(), self.params.eq_high_q.smoothed.next()),
+            ];
             let reverb_step_alg: reverb::ReverbType = self.params.reverb_step_alg.value();
             let reverb_sidechain: f32 = self.params.reverb_sidechain.smoothed.next();
+            let key_source: KeySource = self.params.key_source.value();
+            let duck_attack: f32 = self.params.duck_attack.value();
+            let duck_release: f32 = self.params.duck_release.value();
+            let duck_hold: f32 = self.params.duck_hold.value();
+            let duck_ratio: f32 = self.params.duck_ratio.value();
+            let duck_knee: f32 = self.params.duck_knee.value();
+            let duck_detection: (DetectionMode, f32) = (self.params.duck_detection.value(), self.params.duck_link.value());
+            let duck_bands: usize = self.params.duck_bands.value() as usize;
+            let duck_crossovers: (f32, f32) = (self.params.duck_low_crossover.value(), self.params.duck_high_crossover.value());
+            let band_thresholds: [f32; MAX_DUCK_BANDS] = [
+                self.params.duck_low_threshold.smoothed.next(),
+                self.params.duck_mid_threshold.smoothed.next(),
+                self.params.duck_high_threshold.smoothed.next(),
+            ];
+            // A single band keeps the broadband threshold and range
+            let (duck_thresholds, duck_depths): ([f32; MAX_DUCK_BANDS], [f32; MAX_DUCK_BANDS]) = if duck_bands == 1 {
+                ([reverb_sidechain; MAX_DUCK_BANDS], [self.params.duck_range.value(); MAX_DUCK_BANDS])
+            } else {
+                (band_thresholds, [self.params.duck_low_depth.value(), self.params.duck_mid_depth.value(), self.params.duck_high_depth.value()])
+            };
             let output_gain: f32 = self.params.output_gain.smoothed.next();
-            let dry_wet: f32 = self.params.dry_wet.value();
-
-            // Split left and right same way original subhoofer did
-            let in_l: f32 = *channel_samples.get_mut(0).unwrap();
-            let in_r: f32 = *channel_samples.get_mut(1).unwrap();
-
-            let reverb_width: f32;
-            // Make extra width if our input signal is mono
-            if in_l == in_r {
-                reverb_width = self.params.reverb_width.smoothed.next() * 3.0;
+            let limiter_mode: LimiterMode = self.params.limiter_mode.value();
+            let limiter_ceiling: f32 = self.params.limiter_ceiling.smoothed.next();
+            let runaway_protection: bool = self.params.runaway_protection.value();
+            let ambi_spread: f32 = self.params.ambi_spread.smoothed.next();
+            let dry_wet: f32 = self.params.dry_wet.smoothed.next();
+            let dry_level: f32 = self.params.dry_level.smoothed.next();
+            let wet_level: f32 = self.params.wet_level.smoothed.next();
+
+            // Send mode fades in and out instead of clicking
+            let send_target: f32 = if self.params.send_mode.value() { 1.0 } else { 0.0 };
+            let send_step: f32 = 1.0 / (SEND_FADE_MS * 0.001 * self.sample_rate);
+            self.send_fade += (send_target - self.send_fade).clamp(-send_step, send_step);
+
+            let auto_gain_target: f32 = if self.params.auto_gain.value() { self.auto_gain_target } else { 1.0 };
+            self.auto_gain = auto_gain_target + (self.auto_gain - auto_gain_target) * self.auto_gain_weight;
+            // Mono into stereo always gets widened, otherwise it's up to the user
+            let mono_widen: bool = self.params.mono_widen.value() || (self.num_input_channels == 1 && self.num_output_channels > 1);
+
+            // Split left and right same way original subhoofer did, a mono input feeds both sides
+            let mut in_l: f32 = *channel_samples.get_mut(0).unwrap();
+            let mut in_r: f32 = if self.num_input_channels > 1 { *channel_samples.get_mut(1).unwrap() } else { in_l };
+
+            // Surround stacks are fed from a mono sum of every input except the LFE
+            let mut surround_feed: f32 = 0.0;
+            if !self.surround_arrays.is_empty() {
+                let mut feed_channels: f32 = 0.0;
+                for channel in 0..self.num_input_channels {
+                    if !self.is_lfe(channel) {
+                        surround_feed += *channel_samples.get_mut(channel).unwrap();
+                        feed_channels += 1.0;
+                    }
+                }
+                surround_feed /= feed_channels;
             }
-            else {
-                reverb_width = self.params.reverb_width.smoothed.next();
+
+            // Pick what the ducking listens to, surround layouts listen to the whole mono sum
+            let (sc_l, sc_r) = match sidechain {
+                Some(sc_channels) if !sc_channels.is_empty() => {
+                    let sc_l: f32 = sc_channels[0][sample_index];
+                    (sc_l, sc_channels.get(1).map_or(sc_l, |sc_channel| sc_channel[sample_index]))
+                },
+                _ => (0.0, 0.0),
+            };
+            let (key_l, key_r) = if !self.surround_arrays.is_empty() && key_source == KeySource::Internal {
+                (surround_feed, surround_feed)
+            } else {
+                key_source.key(in_l, in_r, sc_l, sc_r)
+            };
+
+            // Lookahead delays everything we hear while the keys above stay early
+            for channel in 0..self.num_input_channels {
+                let sample = channel_samples.get_mut(channel).unwrap();
+                *sample = self.lookahead.process(channel, *sample);
+                input_peak = input_peak.max(sample.abs());
             }
+            surround_feed = self.lookahead.process(LOOKAHEAD_FEED_SLOT, surround_feed);
+            self.lookahead.advance();
+            in_l = *channel_samples.get_mut(0).unwrap();
+            in_r = if self.num_input_channels > 1 { *channel_samples.get_mut(1).unwrap() } else { in_l };
+
+            // How busy the input is, signed by the direction so it can push the targets either way
+            self.envelope.update_times(self.params.env_attack.value(), self.params.env_release.value(), self.sample_rate);
+            let envelope_mod: f32 = match self.params.env_direction.value() {
+                EnvelopeDirection::LouderMore => self.envelope.process(in_l, in_r),
+                EnvelopeDirection::LouderLess => -self.envelope.process(in_l, in_r),
+            };
+            let decay_mod: f32 = envelope_mod * self.params.env_decay_depth.smoothed.next();
+            let mix_mod: f32 = envelope_mod * self.params.env_mix_depth.smoothed.next();
+            let high_cut: f32 = (reverb_high_cut * 2.0_f32.powf(envelope_mod * self.params.env_cutoff_depth.smoothed.next() * ENV_CUTOFF_OCTAVES))
+                .clamp(MIN_LOW_PASS_HZ, MAX_LOW_PASS_HZ);
+
+            // Make extra width for mono sources
+            let reverb_width: f32 = if mono_widen {
+                self.params.reverb_width.smoothed.next() * 3.0
+            } else {
+                self.params.reverb_width.smoothed.next()
+            };
             
             ///////////////////////////////////////////////////////////////////////
             
-            let temp_l_len: i32 = self.reverb_l_array.len() as i32;
-            let temp_r_len: i32 = self.reverb_r_array.len() as i32;
-            let temp_buffer: usize = reverb_delay as usize * 2 as usize;
-            
             let mut update_bool = false;
-            // Create or remove reverb stacks
-            if reverb_stack > temp_l_len
-            {
-                while reverb_stack > self.reverb_l_array.len() as i32
-                {
-                    self.reverb_l_array.push(Reverb::new(Reverb::generate_steps(reverb_delay, reverb_steps, reverb_step_alg),reverb_decay,temp_buffer));
-                }
-                update_bool = true;
-            }
-            else if reverb_stack < temp_l_len
-            {
-                while reverb_stack < self.reverb_l_array.len() as i32
-                {
-                    self.reverb_l_array.pop();
-                }
-                update_bool = true;
-            }
-            if reverb_stack > temp_r_len
+            // Enable or disable reverb stacks, they are all allocated already
+            if reverb_stack != self.active_stacks
             {
-                while reverb_stack > self.reverb_r_array.len() as i32
-                {
-                    self.reverb_r_array.push(Reverb::new(Reverb::generate_steps(reverb_delay, reverb_steps, reverb_step_alg),reverb_decay,temp_buffer));
+                // Stacks coming back would otherwise replay whatever they held when they were disabled
+                for (left, right) in 
+                    self.reverb_l_array.iter_mut().zip(
+                    self.reverb_r_array.iter_mut()).take(reverb_stack).skip(self.active_stacks) {
+                    left.clear();
+                    right.clear();
                 }
-                update_bool = true;
-            }
-            if reverb_stack < temp_r_len
-            {
-                while reverb_stack < self.reverb_r_array.len() as i32
-                {
-                    self.reverb_r_array.pop();
+                for stacks in self.surround_arrays.iter_mut() {
+                    for reverb in stacks.iter_mut().take(reverb_stack).skip(self.active_stacks) {
+                        reverb.clear();
+                    }
                 }
+                self.active_stacks = reverb_stack;
                 update_bool = true;
             }
             // If any other knobs have changed and we need to update our struct
@@ -548,8 +2028,15 @@ impl Plugin for Gain {
                reverb_decay != self.prev_reverb_decay ||
                reverb_low_cut != self.prev_low_cut ||
                reverb_high_cut != self.prev_high_cut ||
-               width_offset != self.prev_width_offset
+               mono_widen != self.prev_mono_widen ||
+               mid_side != self.prev_mid_side
             {
+                // The buffers hold left and right or mid and side, switching means starting over
+                if mid_side.0 != self.prev_mid_side.0 {
+                    for reverb in self.reverb_l_array.iter_mut().chain(self.reverb_r_array.iter_mut()) {
+                        reverb.clear();
+                    }
+                }
                 update_bool = true;
                 self.prev_reverb_alg = reverb_step_alg;
                 self.prev_reverb_steps = reverb_steps;
@@ -557,14 +2044,47 @@ impl Plugin for Gain {
                 self.prev_reverb_decay = reverb_decay;
                 self.prev_low_cut = reverb_low_cut;
                 self.prev_high_cut = reverb_high_cut;
-                self.prev_width_offset = width_offset;
+                self.prev_mono_widen = mono_widen;
+                self.prev_mid_side = mid_side;
+            }
+            if update_bool {
+                let (left_decay, right_decay) = if mid_side.0 { (mid_side.1, mid_side.2) } else { (reverb_decay, reverb_decay) };
+                self.auto_gain_target = Gain::auto_gain(self.active_stacks, left_decay, right_decay);
             }
 
-            if reverb_sidechain != self.prev_sc_threshold {
-                let temp_sr = _context.transport().sample_rate;
-                self.ducking_l.update_threshold(reverb_sidechain, temp_sr);
-                self.ducking_r.update_threshold(reverb_sidechain, temp_sr);
-                self.prev_sc_threshold = reverb_sidechain;
+            let temp_sr = self.sample_rate;
+            for band in 0..MAX_DUCK_BANDS {
+                if duck_thresholds[band] != self.prev_duck_thresholds[band] {
+                    self.ducking.update_band_threshold(band, duck_thresholds[band], temp_sr);
+                    self.prev_duck_thresholds[band] = duck_thresholds[band];
+                }
+                let duck_curve: (f32, f32, f32) = (duck_ratio, duck_depths[band], duck_knee);
+                if duck_curve != self.prev_duck_curves[band] {
+                    self.ducking.update_band_curve(band, duck_ratio, duck_depths[band], duck_knee);
+                    self.prev_duck_curves[band] = duck_curve;
+                }
+            }
+            if duck_crossovers != self.prev_duck_crossovers {
+                self.ducking.update_crossovers(duck_crossovers.0, duck_crossovers.1, temp_sr);
+                self.prev_duck_crossovers = duck_crossovers;
+            }
+            self.ducking.set_band_count(duck_bands);
+            if duck_attack != self.prev_duck_attack {
+                self.ducking.update_attack(duck_attack, temp_sr);
+                self.prev_duck_attack = duck_attack;
+            }
+            if duck_release != self.prev_duck_release {
+                self.ducking.update_release(duck_release, temp_sr);
+                self.prev_duck_release = duck_release;
+            }
+            if duck_hold != self.prev_duck_hold {
+                self.ducking.update_hold(duck_hold, temp_sr);
+                self.prev_duck_hold = duck_hold;
+            }
+            if duck_detection != self.prev_duck_detection {
+                let (detection_mode, duck_link) = duck_detection;
+                self.ducking.update_detection(detection_mode, duck_link);
+                self.prev_duck_detection = duck_detection;
             }
 
             if update_bool == true
@@ -573,43 +2093,156 @@ impl Plugin for Gain {
                 // Update our reverb stacks
                 for (left, right) in 
                     self.reverb_l_array.iter_mut().zip(
-                    self.reverb_r_array.iter_mut()) {
+                    self.reverb_r_array.iter_mut()).take(self.active_stacks) {
                     // Integer division to scale delay with amount of stack
-                    left.update(Reverb::generate_steps(reverb_delay/counter, reverb_steps, reverb_step_alg), reverb_decay);
-                    right.update(Reverb::generate_steps(reverb_delay/counter, reverb_steps, reverb_step_alg), reverb_decay);
-
-                    // Haas offset is here since the reverb buffers need to change
-                    if width_offset != 0 {
-                        if width_offset > 0 {
-                            left.shift_buffer(width_offset);
-                        }
-                        else {
-                            right.shift_buffer(-width_offset);
-                        }
-                    }
+                    // In mid/side the left stacks are the mid and the right stacks the side
+                    let (left_decay, right_decay) = if mid_side.0 { (mid_side.1, mid_side.2) } else { (reverb_decay, reverb_decay) };
+                    Reverb::generate_steps(reverb_delay/counter, reverb_steps, reverb_step_alg, &mut self.step_scratch);
+                    left.update(&self.step_scratch, left_decay);
+                    // Detune the right side when widening so identical inputs still give a stereo tail
+                    let right_delay = if mono_widen { (reverb_delay as f32 * MONO_WIDEN_DETUNE) as i32 } else { reverb_delay };
+                    Reverb::generate_steps(right_delay/counter, reverb_steps, reverb_step_alg, &mut self.step_scratch);
+                    right.update(&self.step_scratch, right_decay);
 
                     counter += 1;
                 }
 
-                // Update our filter(s)
-                self.filter_lowpass.update_params(reverb_high_cut, true);
-                self.filter_highpass.update_params(reverb_low_cut, false);
+                // Surround stacks follow the same settings with their own stretch
+                for (detune, stacks) in SURROUND_DETUNE.iter().zip(self.surround_arrays.iter_mut()) {
+                    let surround_delay = (reverb_delay as f32 * detune) as i32;
+                    for (reverb, counter) in stacks.iter_mut().take(self.active_stacks).zip(1..) {
+                        Reverb::generate_steps(surround_delay/counter, reverb_steps, reverb_step_alg, &mut self.step_scratch);
+                        reverb.update(&self.step_scratch, reverb_decay);
+                    }
+                }
+
+                if self.ambisonic_output {
+                    self.update_ambi_directions();
+                }
+            }
+
+            // Moving the filters somewhere else means their old state no longer belongs there
+            let placement_changed = filter_placement != self.prev_filter_placement;
+            if placement_changed {
+                self.filter_lowpass.reset();
+                self.filter_highpass.reset();
+                for (lowpass, highpass) in self.surround_filters.iter_mut().chain(self.ambi_filters.iter_mut()) {
+                    lowpass.reset();
+                    highpass.reset();
+                }
+                let in_loop = filter_placement == FilterPlacement::InLoop;
+                for reverb in self.reverb_l_array.iter_mut().chain(self.reverb_r_array.iter_mut()).chain(self.surround_arrays.iter_mut().flatten()) {
+                    reverb.set_filter_feedback(in_loop);
+                }
+                self.prev_filter_placement = filter_placement;
+            }
+
+            // Update our filter(s), slope and Q changes don't need the stacks rebuilt
+            // Recalculating every wet, surround and in-loop filter on every sample is too much, so the envelope only gets a say at control rate
+            self.cutoff_counter = (self.cutoff_counter + 1) % ENV_CUTOFF_INTERVAL;
+            let cutoff_moved = self.cutoff_counter == 0 && high_cut != self.prev_mod_high_cut;
+            if update_bool || placement_changed || filter_shape != self.prev_filter_shape || cutoff_moved {
+                let (low_cut_slope, low_cut_q, high_cut_slope, high_cut_q) = filter_shape;
+                self.filter_lowpass.update_params(high_cut, high_cut_slope, high_cut_q, temp_sr);
+                self.filter_highpass.update_params(reverb_low_cut, low_cut_slope, low_cut_q, temp_sr);
+                for (lowpass, highpass) in self.surround_filters.iter_mut().chain(self.ambi_filters.iter_mut()) {
+                    lowpass.update_params(high_cut, high_cut_slope, high_cut_q, temp_sr);
+                    highpass.update_params(reverb_low_cut, low_cut_slope, low_cut_q, temp_sr);
+                }
+                // Every stack carries its own copy when filtering inside the feedback
+                if filter_placement == FilterPlacement::InLoop {
+                    for reverb in self.reverb_l_array.iter_mut().take(self.active_stacks)
+                        .chain(self.reverb_r_array.iter_mut().take(self.active_stacks))
+                        .chain(self.surround_arrays.iter_mut().flat_map(|stacks| stacks.iter_mut().take(self.active_stacks))) {
+                        reverb.update_feedback_filters(reverb_low_cut, high_cut, filter_shape, temp_sr);
+                    }
+                }
+                self.prev_filter_shape = filter_shape;
+                self.prev_mod_high_cut = high_cut;
+            }
+            if tilt != self.prev_tilt {
+                self.wet_tilt.update_params(tilt, temp_sr);
+                for tilt_eq in self.surround_tilt.iter_mut().chain(self.ambi_tilt.iter_mut()) {
+                    tilt_eq.update_params(tilt, temp_sr);
+                }
+                self.prev_tilt = tilt;
+            }
+            // Stacks that just became active need the age too
+            if update_bool || age != self.prev_age {
+                for reverb in self.reverb_l_array.iter_mut().take(self.active_stacks)
+                    .chain(self.reverb_r_array.iter_mut().take(self.active_stacks))
+                    .chain(self.surround_arrays.iter_mut().flat_map(|stacks| stacks.iter_mut().take(self.active_stacks))) {
+                    reverb.update_age(age * MAX_AGE_CUT_DB, temp_sr);
+                }
+                self.prev_age = age;
+            }
+            // Only one of the input or the stacks saturates at a time
+            if update_bool || saturation != self.prev_saturation {
+                let (saturation_type, saturation_drive, saturation_character, saturation_placement) = saturation;
+                let saturator = Saturator::new(saturation_type, saturation_drive, saturation_character);
+                let (input_saturator, feedback_saturator) = match saturation_placement {
+                    SaturationPlacement::Input => (saturator, Saturator::off()),
+                    SaturationPlacement::Feedback => (Saturator::off(), saturator),
+                };
+                self.input_saturator = input_saturator;
+                for reverb in self.reverb_l_array.iter_mut().take(self.active_stacks)
+                    .chain(self.reverb_r_array.iter_mut().take(self.active_stacks))
+                    .chain(self.surround_arrays.iter_mut().flat_map(|stacks| stacks.iter_mut().take(self.active_stacks))) {
+                    reverb.update_saturation(feedback_saturator);
+                }
+                self.prev_saturation = saturation;
+            }
+            // Envelope moves the feedback only, the delay times stay put
+            if decay_mod != self.prev_decay_mod || (update_bool && decay_mod != 0.0) {
+                let (left_decay, right_decay) = if mid_side.0 { (mid_side.1, mid_side.2) } else { (reverb_decay, reverb_decay) };
+                let max_decay = self.params.reverb_decay.preview_plain(1.0);
+                for (left, right) in self.reverb_l_array.iter_mut().zip(self.reverb_r_array.iter_mut()).take(self.active_stacks) {
+                    left.set_decay(envelope::modulate(left_decay, max_decay, decay_mod));
+                    right.set_decay(envelope::modulate(right_decay, max_decay, decay_mod));
+                }
+                for reverb in self.surround_arrays.iter_mut().flat_map(|stacks| stacks.iter_mut().take(self.active_stacks)) {
+                    reverb.set_decay(envelope::modulate(reverb_decay, max_decay, decay_mod));
+                }
+                self.prev_decay_mod = decay_mod;
+            }
+            if eq_settings != self.prev_eq_settings {
+                self.wet_eq.update_params(eq_settings, temp_sr);
+                for eq in self.surround_eq.iter_mut().chain(self.ambi_eq.iter_mut()) {
+                    eq.update_params(eq_settings, temp_sr);
+                }
+                self.prev_eq_settings = eq_settings;
             }
 
             
 
-            // Set initial
-            processed_sample_l = in_l;
-            processed_sample_r = in_r;
+            // Set initial, pre placement shapes what goes into the stacks
+            let (stack_in_l, stack_in_r) = if filter_placement == FilterPlacement::PreStack {
+                let (highpassed_l, highpassed_r) = self.filter_highpass.filter(in_l, in_r);
+                self.filter_lowpass.filter(highpassed_l, highpassed_r)
+            } else {
+                (in_l, in_r)
+            };
+            let (stack_in_l, stack_in_r) = (self.input_saturator.process(stack_in_l), self.input_saturator.process(stack_in_r));
+            processed_sample_l = stack_in_l;
+            processed_sample_r = stack_in_r;
+
+            // The surround feed is mono so it runs through the left side of the first surround filters
+            let stack_feed: f32 = match self.surround_filters.first_mut() {
+                Some((lowpass, highpass)) if filter_placement == FilterPlacement::PreStack => {
+                    lowpass.filter_mono(highpass.filter_mono(surround_feed))
+                },
+                _ => surround_feed,
+            };
+            let stack_feed = self.input_saturator.process(stack_feed);
 
-            let mut rng = thread_rng();
+            let mut ambi_wet = [0.0_f32; AMBIX_CHANNELS];
             // Process our stacks
-            for (left, right) in 
+            for (stack, (left, right)) in 
                 self.reverb_l_array.iter_mut().zip(
-                self.reverb_r_array.iter_mut()) {
+                self.reverb_r_array.iter_mut()).take(self.active_stacks).enumerate() {
                 // Random Reverb width functionality
                 let calc_width_offset: f32 = if width_random > 0.0 {
-                    let weighted_rand = rng.gen_range(-width_random..width_random);
+                    let weighted_rand = self.rng.range(-width_random, width_random);
                     self.prev_rand_offset = lerp(self.prev_rand_offset, weighted_rand, 0.000053);
                     self.prev_rand_offset
                 } else {
@@ -620,31 +2253,122 @@ impl Plugin for Gain {
                 let widthInv = 1.0 - calc_width_offset*0.1;
                 let mid = (processed_sample_l + processed_sample_r)*0.5;
                 // Process an unwritable buffer for 'freeze' functionality.
-                if reverb_lock == 1 {
-                    let tempSample_l: f32 = left.locked_buffer_process(widthInv * mid + (calc_width_offset) * processed_sample_l);
-                    let tempSample_r: f32 = right.locked_buffer_process(widthInv * mid + (-calc_width_offset) * processed_sample_r);
-
-                    processed_sample_l += self.ducking_l.process(tempSample_l, in_l);
-                    processed_sample_r += self.ducking_r.process(tempSample_r, in_r);
+                let (tempSample_l, tempSample_r) = if mid_side.0 {
+                    // Mid and side each get their own stack then go back to left and right
+                    let side = (processed_sample_l - processed_sample_r)*0.5;
+                    let (mid_out, side_out) = if reverb_lock == 1 {
+                        (left.locked_buffer_process(mid), right.locked_buffer_process(side))
+                    } else {
+                        (left.process(mid), right.process(side))
+                    };
+                    (mid_out + side_out, mid_out - side_out)
+                }
+                else if reverb_lock == 1 {
+                    (left.locked_buffer_process(widthInv * mid + (calc_width_offset) * processed_sample_l),
+                     right.locked_buffer_process(widthInv * mid + (-calc_width_offset) * processed_sample_r))
                 }
                 else {
                     // Process buffer and write to buffer
-                    let tempSample_l: f32 = left.process(widthInv * mid + (calc_width_offset) * processed_sample_l);
-                    let tempSample_r: f32 = right.process(widthInv * mid + (-calc_width_offset) * processed_sample_r);
+                    (left.process(widthInv * mid + (calc_width_offset) * processed_sample_l),
+                     right.process(widthInv * mid + (-calc_width_offset) * processed_sample_r))
+                };
+
+                processed_sample_l += tempSample_l;
+                processed_sample_r += tempSample_r;
+
+                // Each stack pair becomes a reflection, right side mirrored across the front
+                if self.ambisonic_output {
+                    // Same mid and side levels the stereo wet gets after the loop, per stack since the sum is linear
+                    let (ambi_l, ambi_r) = if mid_side.0 {
+                        let stack_mid = (tempSample_l + tempSample_r) * 0.5 * mid_level;
+                        let stack_side = (tempSample_l - tempSample_r) * 0.5 * side_level;
+                        (stack_mid + stack_side, stack_mid - stack_side)
+                    } else {
+                        (tempSample_l, tempSample_r)
+                    };
+                    let [x, y, z] = self.ambi_directions[stack];
+                    ambi_wet[0] += ambi_l + ambi_r;
+                    ambi_wet[1] += ambi_spread * y * (ambi_l - ambi_r);
+                    ambi_wet[2] += ambi_spread * z * (ambi_l + ambi_r);
+                    ambi_wet[3] += ambi_spread * x * (ambi_l + ambi_r);
+                }
+            }
 
-                    processed_sample_l += self.ducking_l.process(tempSample_l, in_l);
-                    processed_sample_r += self.ducking_r.process(tempSample_r, in_r);
+            // Mid and side levels only touch what the stacks added
+            if mid_side.0 {
+                let wet_mid = ((processed_sample_l - stack_in_l) + (processed_sample_r - stack_in_r)) * 0.5 * mid_level;
+                let wet_side = ((processed_sample_l - stack_in_l) - (processed_sample_r - stack_in_r)) * 0.5 * side_level;
+                processed_sample_l = stack_in_l + wet_mid + wet_side;
+                processed_sample_r = stack_in_r + wet_mid - wet_side;
+            }
+
+            // Surround channels only carry the tail, the summed feed would otherwise leak into every speaker
+            let mut surround_wet = [0.0_f32; MAX_SURROUND_CHANNELS];
+            for (index, stacks) in self.surround_arrays.iter_mut().enumerate() {
+                let mut processed_sample: f32 = stack_feed;
+                for reverb in stacks.iter_mut().take(self.active_stacks) {
+                    let temp_sample: f32 = if reverb_lock == 1 {
+                        reverb.locked_buffer_process(processed_sample)
+                    } else {
+                        reverb.process(processed_sample)
+                    };
+                    processed_sample += temp_sample;
+                }
+                surround_wet[index] = processed_sample - stack_feed;
+            }
+
+            // Stereo linked ducking over everything the stacks added, split into bands if asked
+            let wet_l = (processed_sample_l - stack_in_l) * self.auto_gain;
+            let wet_r = (processed_sample_r - stack_in_r) * self.auto_gain;
+            let ((ducked_l, ducked_r), duck_gain_mid) = self.ducking.process(wet_l, wet_r, key_l, key_r);
+            // Send mode also drops the input the stacks were fed from so only the tail is left
+            processed_sample_l = stack_in_l * (1.0 - self.send_fade) + ducked_l;
+            processed_sample_r = stack_in_r * (1.0 - self.send_fade) + ducked_r;
+            reduction_peak = reduction_peak.max(-util::gain_to_db(duck_gain_mid));
+
+            // Channels that aren't left or right take the average over both sides and every band
+            let surround_gain = duck_gain_mid * self.auto_gain;
+            surround_wet.iter_mut().chain(ambi_wet.iter_mut()).for_each(|sample| *sample *= surround_gain);
+
+            // Surround filters and EQ run on pairs of channels
+            for (pair, (((lowpass, highpass), eq), tilt_eq)) in self.surround_filters.iter_mut().zip(self.surround_eq.iter_mut()).zip(self.surround_tilt.iter_mut()).enumerate() {
+                let (mut wet_a, mut wet_b) = (surround_wet[pair * 2], surround_wet[pair * 2 + 1]);
+                if post_filters {
+                    (wet_a, wet_b) = highpass.filter(wet_a, wet_b);
+                    (wet_a, wet_b) = lowpass.filter(wet_a, wet_b);
+                }
+                (wet_a, wet_b) = eq.filter(wet_a, wet_b);
+                (surround_wet[pair * 2], surround_wet[pair * 2 + 1]) = tilt_eq.filter(wet_a, wet_b);
+            }
+
+            // Filtering every B-format channel the same way keeps the directions intact
+            if self.ambisonic_output {
+                for (pair, (((lowpass, highpass), eq), tilt_eq)) in self.ambi_filters.iter_mut().zip(self.ambi_eq.iter_mut()).zip(self.ambi_tilt.iter_mut()).enumerate() {
+                    let (mut wet_a, mut wet_b) = (ambi_wet[pair * 2], ambi_wet[pair * 2 + 1]);
+                    if post_filters {
+                        (wet_a, wet_b) = highpass.filter(wet_a, wet_b);
+                        (wet_a, wet_b) = lowpass.filter(wet_a, wet_b);
+                    }
+                    (wet_a, wet_b) = eq.filter(wet_a, wet_b);
+                    (ambi_wet[pair * 2], ambi_wet[pair * 2 + 1]) = tilt_eq.filter(wet_a, wet_b);
                 }
             }
 
-            let highpassed_l;
-            let highpassed_r;
+            // Post placement filters the finished tail, the other placements already did their filtering
+            if post_filters {
+                let highpassed_l;
+                let highpassed_r;
+
+                // Highpass
+                (highpassed_l, highpassed_r) = self.filter_highpass.filter(processed_sample_l, processed_sample_r);
 
-            // Highpass
-            (highpassed_l, highpassed_r) = self.filter_highpass.filter(processed_sample_l, processed_sample_r);
+                // Lowpass
+                (processed_sample_l, processed_sample_r) = self.filter_lowpass.filter(highpassed_l, highpassed_r);
+            }
 
-            // Lowpass
-            (processed_sample_l, processed_sample_r) = self.filter_lowpass.filter(highpassed_l, highpassed_r);
+            // Parametric EQ and tilt
+            (processed_sample_l, processed_sample_r) = self.wet_eq.filter(processed_sample_l, processed_sample_r);
+            (processed_sample_l, processed_sample_r) = self.wet_tilt.filter(processed_sample_l, processed_sample_r);
 
             // Reverb width
             let widthInv = 1.0 - reverb_width;
@@ -652,6 +2376,22 @@ impl Plugin for Gain {
             processed_sample_l = widthInv * mid + reverb_width * processed_sample_l;
             processed_sample_r = widthInv * mid + reverb_width * processed_sample_r;
 
+            // Haas offset, the delay and side glide so nothing needs rebuilding.
+            // Automation on the old offset delays the other side by that many samples like it used to
+            let width_offset: i32 = self.params.width_offset.value();
+            let (haas_samples, haas_swap) = if width_offset != 0 {
+                (width_offset.unsigned_abs() as f32, width_offset < 0)
+            } else {
+                (haas_samples, self.params.haas_swap.value())
+            };
+            (processed_sample_l, processed_sample_r) = self.haas.process(
+                processed_sample_l,
+                processed_sample_r,
+                haas_samples,
+                self.params.haas_invert.value(),
+                haas_swap,
+            );
+
             // Remove DC Offset with single pole HP
             // Calculated below by Ardura in advance!
             // double sqrt2 = 1.41421356237;
@@ -675,12 +2415,42 @@ impl Plugin for Gain {
             self.prev_processed_in_r = processed_sample_r;
             self.prev_processed_out_r = temp_sample;
             processed_sample_r = temp_sample;
+
+            // Same DC blocker on the surround and B-format channels
+            for (sample, (prev_in, prev_out)) in surround_wet.iter_mut().zip(self.surround_dc.iter_mut())
+                .chain(ambi_wet.iter_mut().zip(self.ambi_dc.iter_mut())) {
+                let temp_sample: f32 = hp_b0 * *sample + hp_b1 * *prev_in - hp_a1 * *prev_out;
+                *prev_in = *sample;
+                *prev_out = temp_sample;
+                *sample = temp_sample;
+            }
                         
             ///////////////////////////////////////////////////////////////////////
 
-            // Calculate dry/wet mix
-            let wet_gain: f32 = dry_wet;
-            let dry_gain: f32 = 1.0 - wet_gain;
+            // Pull the wet out and start the stacks over if they blew up
+            if runaway_protection {
+                let wet_finite = processed_sample_l.is_finite() && processed_sample_r.is_finite()
+                    && surround_wet.iter().chain(ambi_wet.iter()).all(|sample| sample.is_finite());
+                let frame_peak = surround_wet.iter().chain(ambi_wet.iter())
+                    .fold(processed_sample_l.abs().max(processed_sample_r.abs()), |peak, sample| peak.max(sample.abs()));
+                let guard_gain = self.runaway_guard.process(wet_finite, frame_peak);
+                // Multiplying NaN by zero is still NaN so those get replaced outright
+                let guard = |sample: &mut f32| *sample = if wet_finite { *sample * guard_gain } else { 0.0 };
+                guard(&mut processed_sample_l);
+                guard(&mut processed_sample_r);
+                surround_wet.iter_mut().chain(ambi_wet.iter_mut()).for_each(guard);
+                if self.runaway_guard.take_reset() {
+                    self.clear_wet();
+                }
+            }
+
+            wet_peak = wet_peak.max(processed_sample_l.abs()).max(processed_sample_r.abs());
+
+            // Calculate dry/wet mix, equal power so the middle doesn't dip, send mode pushes it all the way wet
+            let dry_wet: f32 = envelope::modulate(dry_wet, 1.0, mix_mod);
+            let mix: f32 = dry_wet + (1.0 - dry_wet) * self.send_fade;
+            let wet_gain: f32 = (mix * f32::consts::FRAC_PI_2).sin() * wet_level;
+            let dry_gain: f32 = (mix * f32::consts::FRAC_PI_2).cos() * dry_level;
             processed_sample_l = in_l * dry_gain + processed_sample_l * wet_gain;
             processed_sample_r = in_r * dry_gain + processed_sample_r * wet_gain;
             
@@ -688,12 +2458,61 @@ impl Plugin for Gain {
             processed_sample_l *= output_gain;
             processed_sample_r *= output_gain;
 
-            // Assign back so we can output our processed sounds
-            *channel_samples.get_mut(0).unwrap() = processed_sample_l;
-            *channel_samples.get_mut(1).unwrap() = processed_sample_r;
+            // Assign back so we can output our processed sounds, folding down to mono if needed
+            if self.ambisonic_output {
+                // Dry mono sits straight ahead, stereo at +-30 degrees
+                let ambi_dry: [f32; AMBIX_CHANNELS] = if self.num_input_channels > 1 {
+                    [in_l + in_r, 0.5 * (in_l - in_r), 0.0, 0.866 * (in_l + in_r)]
+                } else {
+                    [in_l, 0.0, 0.0, in_l]
+                };
+                for (channel, (dry_sample, wet_sample)) in ambi_dry.iter().zip(ambi_wet.iter()).enumerate() {
+                    *channel_samples.get_mut(channel).unwrap() = (dry_sample * dry_gain + wet_sample * wet_gain) * output_gain;
+                }
+            } else if self.num_output_channels > 1 {
+                *channel_samples.get_mut(0).unwrap() = processed_sample_l;
+                *channel_samples.get_mut(1).unwrap() = processed_sample_r;
+            } else {
+                *channel_samples.get_mut(0).unwrap() = (processed_sample_l + processed_sample_r) * 0.5;
+            }
+            for (index, wet_sample) in surround_wet.iter().enumerate().take(self.surround_arrays.len()) {
+                let sample = channel_samples.get_mut(index + 2).unwrap();
+                *sample = (*sample * dry_gain + wet_sample * wet_gain) * output_gain;
+            }
+
+            // Last thing before the host sees it
+            match limiter_mode {
+                LimiterMode::Off => {},
+                LimiterMode::Soft => {
+                    for sample in channel_samples.iter_mut() {
+                        *sample = OutputLimiter::soft_clip(*sample, limiter_ceiling);
+                    }
+                },
+                LimiterMode::Brickwall => {
+                    let frame_peak = channel_samples.iter_mut().fold(0.0_f32, |peak, sample| peak.max(sample.abs()));
+                    let limiter_gain = self.limiter.brickwall_gain(frame_peak, limiter_ceiling);
+                    for sample in channel_samples.iter_mut() {
+                        *sample = (*sample * limiter_gain).clamp(-limiter_ceiling, limiter_ceiling);
+                    }
+                },
+            }
+            for sample in channel_samples.iter_mut() {
+                output_peak = output_peak.max(sample.abs());
+            }
         }
 
-        ProcessStatus::Normal
+        // Always written, so opening the editor shows the current level instead of whatever was left from last time
+        Meters::update(&self.meters.input, input_peak, self.meter_decay_weight, num_samples);
+        Meters::update(&self.meters.wet, wet_peak, self.meter_decay_weight, num_samples);
+        Meters::update(&self.meters.output, output_peak, self.meter_decay_weight, num_samples);
+        Meters::update(&self.meters.gain_reduction, reduction_peak, self.meter_decay_weight, num_samples);
+
+        // Freeze keeps the buffer ringing forever, otherwise tell the host how long the tail is
+        if self.params.reverb_lock.value() == 1 {
+            ProcessStatus::KeepAlive
+        } else {
+            ProcessStatus::Tail(self.estimate_tail_samples())
+        }
     }
 
     const MIDI_INPUT: MidiConfig = MidiConfig::None;
@@ -707,9 +2526,57 @@ impl Plugin for Gain {
         Box::new(|_| ())
     }
 
-    fn filter_state(_state: &mut PluginState) {}
+    fn filter_state(state: &mut PluginState) {
+        // Older presets stored raw filter coefficients. The low pass gets the -3 dB point the old pair of
+        // one-poles really had, filter_state doesn't know the sample rate so that's worked out at 44.1k
+        if let Some(ParamValue::F32(value)) = state.params.get_mut("reverb_high_cut") {
+            if *value < MIN_LOW_PASS_HZ {
+                *value = filters::one_pole_pair_cutoff_hz(*value, MIGRATION_SAMPLE_RATE).clamp(MIN_LOW_PASS_HZ, MAX_LOW_PASS_HZ);
+            }
+        }
+        // The old high pass subtracted both low pass stages from the input so it never had a -3 dB point,
+        // at best it was a notch, so this keeps the Hz its readout showed
+        if let Some(ParamValue::F32(value)) = state.params.get_mut("reverb_low_cut") {
+            if *value < MIN_HIGH_PASS_HZ {
+                *value = *value * 1570.0 + 30.0;
+            }
+        }
+        // The old sample count offset read the left stacks that far ahead when positive and the right
+        // ones when negative, which is the same as delaying the other side by that many samples at 44.1k.
+        // Zeroing it afterwards hands control to the visible Haas knobs, it only stays for automation
+        if let Some(ParamValue::I32(offset)) = state.params.get_mut("width_offset") {
+            if *offset != 0 {
+                let (haas_time, haas_swap) = (offset.unsigned_abs() as f32 / MIGRATION_SAMPLE_RATE * 1000.0, *offset < 0);
+                *offset = 0;
+                state.params.insert(String::from("haas_time"), ParamValue::F32(haas_time));
+                state.params.insert(String::from("haas_swap"), ParamValue::Bool(haas_swap));
+            }
+        }
+
+        // The editor got wider for the tilt and age knobs, don't let any smaller saved size cut them off
+        if let Some(editor_state) = state.fields.get_mut("editor-state") {
+            if let Ok(saved_state) = serde_json::from_str::<EguiState>(editor_state) {
+                let (width, height) = saved_state.size();
+                if width < WIDTH || height < HEIGHT {
+                    let resized_state = EguiState::from_size(width.max(WIDTH), height.max(HEIGHT));
+                    if let Ok(resized_json) = serde_json::to_string(&*resized_state) {
+                        *editor_state = resized_json;
+                    }
+                }
+            }
+        }
+    }
 
-    fn reset(&mut self) {}
+    fn reset(&mut self) {
+        // Clear everything that would otherwise replay stale audio after a seek
+        self.clear_wet();
+        self.limiter.reset();
+        self.runaway_guard.reset();
+        self.lookahead.reset();
+        // Every render from the start gets the same random width
+        self.prev_seed = self.params.random_seed.value();
+        self.rng = XorShift32::new(self.prev_seed as u32);
+    }
 
     fn deactivate(&mut self) {}
 }
@@ -723,6 +2590,8 @@ impl ClapPlugin for Gain {
         ClapFeature::AudioEffect,
         ClapFeature::Stereo,
         ClapFeature::Mono,
+        ClapFeature::Surround,
+        ClapFeature::Ambisonic,
         ClapFeature::Utility,
     ];
 }
@@ -730,20 +2599,8 @@ impl ClapPlugin for Gain {
 impl Vst3Plugin for Gain {
     const VST3_CLASS_ID: [u8; 16] = *b"CanopyReverbArda";
     const VST3_SUBCATEGORIES: &'static [Vst3SubCategory] =
-        &[Vst3SubCategory::Fx, Vst3SubCategory::Reverb];
+        &[Vst3SubCategory::Fx, Vst3SubCategory::Reverb, Vst3SubCategory::Surround];
 }
 
 nih_export_clap!(Gain);
 nih_export_vst3!(Gain);
-
-pub fn format_lp_filter(digits: usize) -> Arc<dyn Fn(f32) -> String + Send + Sync> {
-    Arc::new(move |value| {
-        format!("{:.digits$}", (value * 15.0) + 1.0)
-    })
-}
-
-pub fn format_hp_filter(digits: usize) -> Arc<dyn Fn(f32) -> String + Send + Sync> {
-    Arc::new(move |value| {
-        format!("{:.digits$}", (value * 1570.0) + 30.0)
-    })
-}
@@ -0,0 +1,77 @@
+use std::sync::atomic::Ordering;
+use atomic_float::AtomicF32;
+use nih_plug::prelude::util;
+use nih_plug_egui::egui::{Color32, ProgressBar, RichText, FontId, Ui};
+
+// How long a meter takes to fall back after a peak
+const METER_DECAY_MS: f32 = 300.0;
+// Bottom of the level meters
+const METER_FLOOR_DB: f32 = -60.0;
+// Gain reduction meter shows this much ducking at full scale
+const METER_MAX_REDUCTION_DB: f32 = 24.0;
+
+// Levels written by the audio thread once per buffer and read by the editor
+#[derive(Default)]
+pub struct Meters {
+    pub input: AtomicF32,
+    pub wet: AtomicF32,
+    pub output: AtomicF32,
+    // Stored as positive dB of ducking
+    pub gain_reduction: AtomicF32,
+}
+
+impl Meters {
+    // Per sample falloff for the peak hold
+    pub fn decay_weight(sample_rate: f32) -> f32 {
+        0.25_f32.powf((sample_rate * METER_DECAY_MS / 1000.0).recip())
+    }
+
+    // Jumps straight up to a new peak and falls back smoothly over the buffer
+    pub fn update(meter: &AtomicF32, buffer_peak: f32, decay_weight: f32, num_samples: usize) {
+        let current = meter.load(Ordering::Relaxed);
+        let new_value = if buffer_peak > current {
+            buffer_peak
+        } else {
+            let buffer_decay = decay_weight.powi(num_samples as i32);
+            current * buffer_decay + buffer_peak * (1.0 - buffer_decay)
+        };
+        meter.store(new_value, Ordering::Relaxed);
+    }
+}
+
+// Horizontal peak meter for a linear level
+fn level_meter(ui: &mut Ui, label: &str, meter: &AtomicF32, width: f32, color: Color32) {
+    let level_db = util::gain_to_db(meter.load(Ordering::Relaxed)).max(METER_FLOOR_DB);
+    let fill = 1.0 - level_db / METER_FLOOR_DB;
+    let text = if level_db <= METER_FLOOR_DB { format!("{} -inf", label) } else { format!("{} {:.1} dB", label, level_db) };
+    draw_meter(ui, fill, text, width, color);
+}
+
+// Horizontal meter for how far the ducking is pulling the wet down
+fn reduction_meter(ui: &mut Ui, label: &str, meter: &AtomicF32, width: f32, color: Color32) {
+    let reduction_db = meter.load(Ordering::Relaxed).max(0.0);
+    let fill = (reduction_db / METER_MAX_REDUCTION_DB).min(1.0);
+    draw_meter(ui, fill, format!("{} -{:.1} dB", label, reduction_db), width, color);
+}
+
+fn draw_meter(ui: &mut Ui, fill: f32, text: String, width: f32, color: Color32) {
+    // The bar fill comes from the selection color so tint it just for this widget
+    ui.scope(|ui| {
+        ui.visuals_mut().selection.bg_fill = color;
+        ui.add(
+            ProgressBar::new(fill)
+                .desired_width(width)
+                .text(RichText::new(text).font(FontId::monospace(10.0)))
+        );
+    });
+}
+
+// All four meters stacked, sits beside the sidechain threshold so you can see the ducking react
+pub fn meter_panel(ui: &mut Ui, meters: &Meters, width: f32, color: Color32) {
+    ui.vertical(|ui| {
+        level_meter(ui, "In ", &meters.input, width, color);
+        level_meter(ui, "Wet", &meters.wet, width, color);
+        level_meter(ui, "Out", &meters.output, width, color);
+        reduction_meter(ui, "GR ", &meters.gain_reduction, width, color);
+    });
+}
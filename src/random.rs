@@ -0,0 +1,33 @@
+// Small seeded PRNG for the audio thread, same seed gives the same random width on every bounce
+// by Ardura
+
+// Xorshift32, plenty for wobbling the width and it never allocates
+#[derive(Clone, Copy)]
+pub(crate) struct XorShift32 {
+    state: u32,
+}
+
+impl XorShift32 {
+    pub(crate) fn new(seed: u32) -> Self {
+        // Xorshift gets stuck on zero, scramble the seed first so neighbouring seeds don't start out alike
+        let mut state = seed.wrapping_add(0x9E37_79B9).wrapping_mul(0x85EB_CA6B);
+        state ^= state >> 16;
+        XorShift32 {
+            state: if state == 0 { 0x9E37_79B9 } else { state },
+        }
+    }
+
+    pub(crate) fn next_u32(&mut self) -> u32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        self.state
+    }
+
+    // Uniform in [min, max)
+    pub(crate) fn range(&mut self, min: f32, max: f32) -> f32 {
+        // Top 24 bits fit an f32 mantissa exactly
+        let unit = (self.next_u32() >> 8) as f32 / (1u32 << 24) as f32;
+        min + (max - min) * unit
+    }
+}
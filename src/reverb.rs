@@ -1,6 +1,8 @@
 use std::{sync::Arc, collections::VecDeque};
 use nih_plug_egui::egui::mutex::Mutex;
 use nih_plug::{prelude::Enum};
+use crate::filters::{AgeFilter, FilterSlope, StereoSlopeFilter, BUTTERWORTH_Q};
+use crate::saturation::Saturator;
 
 #[derive(Enum, PartialEq, Eq, Debug, Copy, Clone)]
 pub enum ReverbType{
@@ -26,8 +28,14 @@ pub(crate) struct Reverb {
     decay: f32,
     buffer: Arc<Mutex<VecDeque<f32>>>,
     write_index: Arc<Mutex<usize>>,
-    read_offset: usize,
     buf_changed: bool,
+    // Per instance filters that shape every trip around the feedback loop when enabled
+    filter_feedback: bool,
+    feedback_highpass: StereoSlopeFilter,
+    feedback_lowpass: StereoSlopeFilter,
+    age_filter: AgeFilter,
+    // Shapes what gets written back so the feedback can't grow without bound
+    saturator: Saturator,
 }
 
 impl Reverb {
@@ -38,16 +46,51 @@ impl Reverb {
             buffer: Arc::new(Mutex::new(VecDeque::from(vec![0.0; buffer_size]))),
             write_index: Arc::new(Mutex::new(0)),
             buf_changed: false,
-            read_offset: 0,
+            filter_feedback: false,
+            feedback_highpass: StereoSlopeFilter::new(false, 30.0, FilterSlope::Db12, BUTTERWORTH_Q, 44100.0),
+            feedback_lowpass: StereoSlopeFilter::new(true, 18000.0, FilterSlope::Db12, BUTTERWORTH_Q, 44100.0),
+            age_filter: AgeFilter::new(44100.0),
+            saturator: Saturator::off(),
         }
     }
 
+    // How many dB of highs each trip around the feedback loses
+    pub(crate) fn update_age(&mut self, cut_per_pass_db: f32, sample_rate: f32) {
+        self.age_filter.update_params(cut_per_pass_db, sample_rate);
+    }
+
+    // Saturation applied on every buffer write, Saturator::off() when it lives on the input instead
+    pub(crate) fn update_saturation(&mut self, saturator: Saturator) {
+        self.saturator = saturator;
+    }
+
+    // Turn the feedback filters on or off, they start from silence when switched on
+    pub(crate) fn set_filter_feedback(&mut self, enabled: bool) {
+        if enabled && !self.filter_feedback {
+            self.feedback_highpass.reset();
+            self.feedback_lowpass.reset();
+        }
+        self.filter_feedback = enabled;
+    }
+
+    // Same settings as the wet filters, shape is (low cut slope, low cut Q, high cut slope, high cut Q)
+    pub(crate) fn update_feedback_filters(&mut self, low_cut: f32, high_cut: f32, shape: (FilterSlope, f32, FilterSlope, f32), sample_rate: f32) {
+        let (low_cut_slope, low_cut_q, high_cut_slope, high_cut_q) = shape;
+        // A resonant peak would multiply the loop gain on every pass, so no more than flat Butterworth in here
+        let low_cut_q = low_cut_q.min(BUTTERWORTH_Q);
+        let high_cut_q = high_cut_q.min(BUTTERWORTH_Q);
+        self.feedback_highpass.update_params(low_cut, low_cut_slope, low_cut_q, sample_rate);
+        self.feedback_lowpass.update_params(high_cut, high_cut_slope, high_cut_q, sample_rate);
+    }
+
     // Update to new delay times + decay when a parameter changes that affects either
-    pub fn update(&mut self, delay_times: VecDeque<i32>, decay: f32) {
+    pub fn update(&mut self, delay_times: &VecDeque<i32>, decay: f32) {
         let mut buffer_lock = self.buffer.lock();
         let mut delay_times_lock = self.delay_times.lock();
-        if *delay_times_lock != delay_times {
-            *delay_times_lock = delay_times;
+        if *delay_times_lock != *delay_times {
+            // Copy into the existing deque, reserve() made sure it has room
+            delay_times_lock.clear();
+            delay_times_lock.extend(delay_times.iter().copied());
             self.buf_changed = true;
         }
         self.decay = decay;
@@ -58,14 +101,8 @@ impl Reverb {
             let buffer_len = buffer_lock.len();
 
             if new_buffer_len > buffer_len {
-                let mut temp_buffer = VecDeque::from(vec![0.0; new_buffer_len]);
-                //temp_buffer[..buffer_len].copy_from_slice(&buffer_lock);
-                // Copy elements one by one from buffer_lock to temp_buffer
-                for (i, val) in buffer_lock.iter().enumerate() {
-                    temp_buffer[i] = *val;
-                }
-                *buffer_lock = temp_buffer;
-
+                // Keeps the old samples at the front, capacity is reserved up front so this doesn't allocate
+                buffer_lock.resize(new_buffer_len, 0.0);
             } else if new_buffer_len < buffer_len {
                 if *self.write_index.lock() >= new_buffer_len {
                     *self.write_index.lock() = new_buffer_len - 1;
@@ -78,9 +115,55 @@ impl Reverb {
         drop(delay_times_lock);
     }
 
-    // This creates the vector for update() function's delay_times input
-    pub(crate) fn generate_steps(input_number: i32, number_of_integers: i32, algorithm: ReverbType) -> VecDeque<i32> {
-        let mut output_vector = VecDeque::new();
+    // Just the feedback amount, for modulation that shouldn't touch the delay times
+    pub(crate) fn set_decay(&mut self, decay: f32) {
+        self.decay = decay;
+    }
+
+    // Reserve enough buffer and delay times that update() never has to allocate on the audio thread
+    pub(crate) fn reserve(&mut self, capacity: usize, max_steps: usize) {
+        let mut buffer_lock = self.buffer.lock();
+        let buffer_len = buffer_lock.len();
+        buffer_lock.reserve(capacity.saturating_sub(buffer_len));
+        let mut delay_times_lock = self.delay_times.lock();
+        let delay_times_len = delay_times_lock.len();
+        delay_times_lock.reserve(max_steps.saturating_sub(delay_times_len));
+    }
+
+    // Silence the buffer so nothing old gets replayed
+    pub(crate) fn clear(&mut self) {
+        self.buffer.lock().iter_mut().for_each(|sample| *sample = 0.0);
+        *self.write_index.lock() = 0;
+        self.feedback_highpass.reset();
+        self.feedback_lowpass.reset();
+        self.age_filter.reset();
+    }
+
+    // Largest buffer update() can end up asking for across every algorithm and step count
+    pub(crate) fn max_buffer_size(max_delay: i32, max_steps: i32) -> usize {
+        let algorithms = [
+            ReverbType::LinearSmall,
+            ReverbType::ExpSwirl,
+            ReverbType::GeoPhase,
+            ReverbType::QuadMetal,
+            ReverbType::SpecificSwirl,
+            ReverbType::ChaosSteps,
+            ReverbType::GoldenRatio,
+        ];
+        let mut delay_times = VecDeque::with_capacity(max_steps.max(0) as usize);
+        algorithms.iter()
+            .flat_map(|alg| (2..=max_steps).map(move |steps| (*alg, steps)))
+            .map(|(alg, steps)| {
+                Reverb::generate_steps(max_delay, steps, alg, &mut delay_times);
+                delay_times.iter().sum::<i32>().max(0) as usize * 2
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    // This fills the delay_times input for update(), the caller keeps the deque so nothing gets allocated
+    pub(crate) fn generate_steps(input_number: i32, number_of_integers: i32, algorithm: ReverbType, output_vector: &mut VecDeque<i32>) {
+        output_vector.clear();
         for i in 1..number_of_integers {
             match algorithm {
                 // Linear small
@@ -137,7 +220,6 @@ impl Reverb {
                 },
             }
         }
-        output_vector
     }
 
     // Process a writable buffer
@@ -155,20 +237,25 @@ impl Reverb {
             if buffer_len == 0 {
                 return input;
             }
-            read_index = (self.read_offset + write_index + *delay_time as usize) % buffer_len;
+            read_index = (write_index + *delay_time as usize) % buffer_len;
             
             delayed_sample = buffer_lock[read_index] * self.decay;
             if delayed_sample < 1e-6 as f32 {
                 delayed_sample = 0.0;
             }
         }
+        if self.filter_feedback {
+            delayed_sample = self.feedback_lowpass.filter_mono(self.feedback_highpass.filter_mono(delayed_sample));
+        }
+        // Age only builds up while the buffer keeps getting written
+        delayed_sample = self.age_filter.filter_mono(delayed_sample);
         output = input + delayed_sample;
 
         if write_index >= buffer_len {
             write_index = 0;
         }
 
-        buffer_lock[write_index] = output;
+        buffer_lock[write_index] = self.saturator.process(output);
         *self.write_index.lock() = write_index + 1;
 
         drop(buffer_lock);
@@ -192,13 +279,16 @@ impl Reverb {
             if buffer_len == 0 {
                 return input;
             }
-            read_index = (self.read_offset + write_index + *delay_time as usize) % buffer_len;
+            read_index = (write_index + *delay_time as usize) % buffer_len;
             
             delayed_sample = buffer_lock[read_index] * self.decay;
             if delayed_sample < 1e-6 as f32 {
                 delayed_sample = 0.0;
             }
         }
+        if self.filter_feedback {
+            delayed_sample = self.feedback_lowpass.filter_mono(self.feedback_highpass.filter_mono(delayed_sample));
+        }
 
         if write_index >= buffer_len {
             write_index = 0;
@@ -215,13 +305,28 @@ impl Reverb {
         delayed_sample
     }
 
-    // This is kind of a way to create an offset by shifting the write spot
-    pub(crate) fn shift_buffer(&mut self, amount: i32) {
-        // Lock these so at the time of modification nothing changes
-        let buf_lock = self.buffer.lock();
-        let write_index = *self.write_index.lock();
-        self.read_offset = amount as usize;
-        drop(buf_lock);
-        drop(write_index);
+    // Rough power one stack adds on top of its input for a steady noise input, loop length doesn't change it
+    pub(crate) fn energy_gain(decay: f32) -> f32 {
+        let decay_squared = decay.clamp(0.0, 0.999).powi(2);
+        decay_squared / (1.0 - decay_squared)
+    }
+
+    // Estimate how long this stack keeps ringing once its input goes silent
+    pub(crate) fn tail_samples(&self) -> u32 {
+        let buffer_len = self.buffer.lock().len();
+        if buffer_len == 0 || self.decay <= 0.0 {
+            return 0;
+        }
+
+        // Only the last delay time ends up being read in process(), and it is read ahead of the
+        // write index so one trip around the feedback loop is nearly a full buffer length
+        let last_delay = self.delay_times.lock().back().copied().unwrap_or(0).max(0) as usize;
+        let loop_length = buffer_len - last_delay % buffer_len;
+
+        // Number of trips until the feedback has dropped by 60dB
+        let passes = (0.001_f32.ln() / self.decay.min(0.9999).ln()).ceil();
+
+        // Float to int casts saturate so a near-infinite tail just becomes u32::MAX
+        (loop_length as f32 * passes) as u32
     }
 }
@@ -0,0 +1,126 @@
+// Output limiter and a guard that pulls the wet out when the stacks run away
+// by Ardura
+
+use nih_plug::prelude::{util, Enum};
+
+// Wet level that counts as running away once it stays there
+const RUNAWAY_LEVEL_DB: f32 = 24.0;
+// How long the wet has to stay above that before the guard trips
+const RUNAWAY_HOLD_MS: f32 = 100.0;
+// Fade out before clearing the stacks and fade back in after
+const RUNAWAY_FADE_MS: f32 = 20.0;
+// Brickwall gain recovery
+const LIMITER_RELEASE_MS: f32 = 100.0;
+
+#[derive(Enum, PartialEq, Eq, Debug, Copy, Clone)]
+pub enum LimiterMode {
+    #[name = "Limit:Off"]
+    Off,
+    #[name = "Limit:Soft"]
+    Soft,
+    #[name = "Limit:Brickwall"]
+    Brickwall,
+}
+
+// Linked across every output channel so the image doesn't shift when it clamps down
+pub(crate) struct OutputLimiter {
+    gain: f32,
+    release_weight: f32,
+}
+
+impl OutputLimiter {
+    pub(crate) fn new(sample_rate: f32) -> Self {
+        OutputLimiter {
+            gain: 1.0,
+            release_weight: (-1.0 / (LIMITER_RELEASE_MS * 0.001 * sample_rate)).exp(),
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.gain = 1.0;
+    }
+
+    // Gain for this frame, drops instantly to catch the peak and recovers over the release
+    pub(crate) fn brickwall_gain(&mut self, frame_peak: f32, ceiling: f32) -> f32 {
+        let target = if frame_peak > ceiling { ceiling / frame_peak } else { 1.0 };
+        self.gain = if target < self.gain {
+            target
+        } else {
+            target + (self.gain - target) * self.release_weight
+        };
+        self.gain
+    }
+
+    // Tanh knee that never passes the ceiling
+    pub(crate) fn soft_clip(sample: f32, ceiling: f32) -> f32 {
+        ceiling * (sample / ceiling).tanh()
+    }
+}
+
+// Watches the wet for NaN/inf or a sustained level nobody wants, then fades it out so the stacks can be cleared
+pub(crate) struct RunawayGuard {
+    over_samples: usize,
+    hold_samples: usize,
+    fade: f32,
+    fade_step: f32,
+    tripped: bool,
+    reset_pending: bool,
+}
+
+impl RunawayGuard {
+    pub(crate) fn new(sample_rate: f32) -> Self {
+        RunawayGuard {
+            over_samples: 0,
+            hold_samples: (RUNAWAY_HOLD_MS * 0.001 * sample_rate) as usize,
+            fade: 1.0,
+            fade_step: 1.0 / (RUNAWAY_FADE_MS * 0.001 * sample_rate),
+            tripped: false,
+            reset_pending: false,
+        }
+    }
+
+    // Returns the wet gain for this frame, zero straight away if anything wasn't finite
+    pub(crate) fn process(&mut self, wet_finite: bool, wet_peak: f32) -> f32 {
+        if !wet_finite {
+            // Nothing to fade when the samples are already garbage
+            self.fade = 0.0;
+            self.tripped = false;
+            self.over_samples = 0;
+            self.reset_pending = true;
+            return 0.0;
+        }
+
+        if wet_peak > util::db_to_gain(RUNAWAY_LEVEL_DB) {
+            self.over_samples += 1;
+            if self.over_samples >= self.hold_samples {
+                self.tripped = true;
+            }
+        } else {
+            self.over_samples = 0;
+        }
+
+        if self.tripped {
+            self.fade = (self.fade - self.fade_step).max(0.0);
+            if self.fade <= 0.0 {
+                self.tripped = false;
+                self.over_samples = 0;
+                self.reset_pending = true;
+            }
+        } else {
+            self.fade = (self.fade + self.fade_step).min(1.0);
+        }
+        self.fade
+    }
+
+    // True once per trip, when the stacks should be cleared
+    pub(crate) fn take_reset(&mut self) -> bool {
+        std::mem::take(&mut self.reset_pending)
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.over_samples = 0;
+        self.fade = 1.0;
+        self.tripped = false;
+        self.reset_pending = false;
+    }
+}
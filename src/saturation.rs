@@ -0,0 +1,86 @@
+// Waveshapers for the input or the feedback write, keeps runaway stacks from growing forever
+// by Ardura
+
+use std::f32;
+use nih_plug::prelude::Enum;
+
+/// Which transfer curve the saturation uses
+#[derive(Enum, PartialEq, Eq, Debug, Copy, Clone)]
+pub enum SaturationType {
+	#[name = "Sat:Off"]
+	Off,
+	#[name = "Sat:Tanh"]
+	Tanh,
+	#[name = "Sat:Tape"]
+	Tape,
+	#[name = "Sat:Tube"]
+	Tube,
+	#[name = "Sat:Hard Clip"]
+	HardClip,
+}
+
+/// Where the saturation sits, the feedback placement shapes every write back into each stack's buffer
+#[derive(Enum, PartialEq, Eq, Debug, Copy, Clone)]
+pub enum SaturationPlacement {
+	#[name = "Sat:Input"]
+	Input,
+	#[name = "Sat:Feedback"]
+	Feedback,
+}
+
+/// Stateless waveshaper, normalized so full scale stays full scale and more drive only makes it denser
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) struct Saturator {
+	curve: SaturationType,
+	drive: f32,
+	character: f32,
+	makeup: f32,
+}
+
+impl Saturator {
+	/// Does the powf and the make-up, so only build a new one when the settings change
+	pub(crate) fn new(curve: SaturationType, drive_db: f32, character: f32) -> Saturator {
+		let mut saturator = Saturator {
+			curve,
+			drive: 10.0_f32.powf(drive_db / 20.0),
+			character: character.clamp(0.0, 1.0),
+			makeup: 1.0,
+		};
+		// Like dividing by tanh(drive), whatever a full scale input comes out at gets brought back to 1
+		let full_scale = saturator.shape(saturator.drive);
+		if full_scale > 0.0 {
+			saturator.makeup = full_scale.recip();
+		}
+		saturator
+	}
+
+	/// Passes everything through untouched
+	pub(crate) fn off() -> Saturator {
+		Saturator::new(SaturationType::Off, 0.0, 0.0)
+	}
+
+	/// The bare curve without any drive or make-up
+	fn shape(&self, driven: f32) -> f32 {
+		match self.curve {
+			SaturationType::Off => driven,
+			SaturationType::Tanh => driven.tanh(),
+			// Softer knee than tanh that keeps rising slowly instead of flattening out
+			SaturationType::Tape => f32::consts::FRAC_2_PI * (f32::consts::FRAC_PI_2 * driven).atan(),
+			// Biased tanh for even harmonics, the bias is taken back out so silence stays at zero
+			SaturationType::Tube => {
+				let bias = 0.3 * self.character;
+				((driven + bias).tanh() - bias.tanh()) / (1.0 - bias.tanh() * bias.tanh())
+			},
+			SaturationType::HardClip => driven.clamp(-1.0, 1.0),
+		}
+	}
+
+	/// Drive pushes the signal into the curve, the make-up keeps full scale where it was and character blends the curve in
+	pub(crate) fn process(&self, input: f32) -> f32 {
+		if self.curve == SaturationType::Off {
+			return input;
+		}
+		let shaped = self.shape(input * self.drive) * self.makeup;
+		input + (shaped - input) * self.character
+	}
+}
@@ -0,0 +1,173 @@
+// ui_eq.rs - egui + nih-plug frequency response curve for the wet EQ
+// Drag a handle to move its band's frequency and gain, scroll over it for Q, double click to flatten it
+
+use nih_plug::prelude::{FloatParam, Param, ParamSetter};
+use nih_plug_egui::egui::{
+    epaint::PathShape, pos2, vec2, Align2, Color32, FontId, Pos2, Rect, Response, Sense, Shape, Stroke, Ui, Vec2, Widget,
+};
+
+use crate::filters::{FourBandEq, EQ_BANDS};
+
+// Used until the plugin has been initialized and knows the real sample rate
+const DEFAULT_SAMPLE_RATE: f32 = 44100.0;
+const MIN_FREQUENCY: f32 = 20.0;
+const MAX_FREQUENCY: f32 = 20000.0;
+// Vertical range shown above and below 0 dB
+const DISPLAY_RANGE_DB: f32 = 18.0;
+const HANDLE_RADIUS: f32 = 6.0;
+
+// The three params behind one band
+pub struct EqBandParams<'a> {
+    pub frequency: &'a FloatParam,
+    pub gain: &'a FloatParam,
+    pub q: &'a FloatParam,
+}
+
+pub struct EqCurve<'a> {
+    bands: [EqBandParams<'a>; EQ_BANDS],
+    param_setter: &'a ParamSetter<'a>,
+    size: Vec2,
+    sample_rate: f32,
+    line_color: Color32,
+    handle_color: Color32,
+}
+
+impl<'a> EqCurve<'a> {
+    pub fn for_params(bands: [EqBandParams<'a>; EQ_BANDS], param_setter: &'a ParamSetter<'a>, size: Vec2) -> Self {
+        EqCurve {
+            bands,
+            param_setter,
+            size,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            line_color: Color32::WHITE,
+            handle_color: Color32::WHITE,
+        }
+    }
+
+    // Draw the curve the way the audio thread hears it, the top band bends near Nyquist
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        if sample_rate > 0.0 {
+            self.sample_rate = sample_rate;
+        }
+    }
+
+    // Specify color for the curve and grid
+    pub fn set_line_color(&mut self, new_color: Color32) {
+        self.line_color = new_color;
+    }
+
+    // Specify color for the band handles
+    pub fn set_handle_color(&mut self, new_color: Color32) {
+        self.handle_color = new_color;
+    }
+
+    // Handle dragging and scrolling on one band's handle
+    fn handle_band(&self, ui: &Ui, rect: Rect, band: &EqBandParams, response: &Response) {
+        if response.drag_started() {
+            self.param_setter.begin_set_parameter(band.frequency);
+            self.param_setter.begin_set_parameter(band.gain);
+        }
+
+        if response.dragged() {
+            if let Some(pointer) = response.interact_pointer_pos() {
+                self.param_setter.set_parameter(band.frequency, x_to_frequency(rect, pointer.x));
+                self.param_setter.set_parameter(band.gain, y_to_db(rect, pointer.y));
+            }
+        }
+
+        if response.drag_released() {
+            self.param_setter.end_set_parameter(band.frequency);
+            self.param_setter.end_set_parameter(band.gain);
+        }
+
+        // Reset the gain on doubleclick
+        if response.double_clicked() {
+            self.param_setter.begin_set_parameter(band.gain);
+            self.param_setter.set_parameter(band.gain, band.gain.default_plain_value());
+            self.param_setter.end_set_parameter(band.gain);
+        }
+
+        // Scroll up for a narrower band
+        let scroll = ui.input().scroll_delta.y;
+        if response.hovered() && scroll != 0.0 {
+            let value = (band.q.unmodulated_normalized_value() + scroll / 1000.0).clamp(0.0, 1.0);
+            self.param_setter.begin_set_parameter(band.q);
+            self.param_setter.set_parameter_normalized(band.q, value);
+            self.param_setter.end_set_parameter(band.q);
+        }
+    }
+}
+
+impl<'a> Widget for EqCurve<'a> {
+    fn ui(self, ui: &mut Ui) -> Response {
+        let response = ui.allocate_response(self.size, Sense::hover());
+        let rect = response.rect;
+        let painter = ui.painter_at(rect);
+        let grid_stroke = Stroke::new(1.0, self.line_color.linear_multiply(0.25));
+
+        // Grid at the decades and every 6 dB
+        painter.rect_stroke(rect, 4.0, grid_stroke);
+        for frequency in [100.0, 1000.0, 10000.0] {
+            let x = frequency_to_x(rect, frequency);
+            painter.line_segment([pos2(x, rect.top()), pos2(x, rect.bottom())], grid_stroke);
+            let label = if frequency >= 1000.0 { format!("{}k", frequency / 1000.0) } else { format!("{}", frequency) };
+            painter.text(pos2(x + 2.0, rect.bottom() - 2.0), Align2::LEFT_BOTTOM, label, FontId::monospace(9.0), self.line_color);
+        }
+        for db in [-12.0, -6.0, 0.0, 6.0, 12.0] {
+            let y = db_to_y(rect, db);
+            painter.line_segment([pos2(rect.left(), y), pos2(rect.right(), y)], grid_stroke);
+        }
+
+        // Build the same EQ the audio thread runs from the current params
+        let settings = self.bands.each_ref().map(|band| (band.frequency.value(), band.gain.value(), band.q.value()));
+        let eq = FourBandEq::new(settings, self.sample_rate);
+        let points: Vec<Pos2> = (0..=rect.width() as usize)
+            .map(|offset| {
+                let x = rect.left() + offset as f32;
+                let db = eq.magnitude_db(x_to_frequency(rect, x), self.sample_rate);
+                pos2(x, db_to_y(rect, db))
+            })
+            .collect();
+        painter.add(Shape::Path(PathShape {
+            points,
+            closed: false,
+            fill: Color32::TRANSPARENT,
+            stroke: Stroke::new(2.0, self.line_color),
+        }));
+
+        // One handle per band sitting on its frequency and gain
+        for (index, band) in self.bands.iter().enumerate() {
+            let center = pos2(frequency_to_x(rect, band.frequency.value()), db_to_y(rect, band.gain.value()));
+            let handle_rect = Rect::from_center_size(center, vec2(HANDLE_RADIUS * 3.0, HANDLE_RADIUS * 3.0));
+            let handle_response = ui
+                .interact(handle_rect, response.id.with(index), Sense::click_and_drag())
+                .on_hover_text(format!("{}\n{}\n{}", band.frequency, band.gain, band.q));
+            self.handle_band(ui, rect, band, &handle_response);
+
+            let fill = if handle_response.hovered() || handle_response.dragged() { self.handle_color } else { self.handle_color.linear_multiply(0.6) };
+            painter.circle_filled(center, HANDLE_RADIUS, fill);
+            painter.text(center, Align2::CENTER_CENTER, format!("{}", index + 1), FontId::monospace(9.0), Color32::BLACK);
+        }
+
+        response
+    }
+}
+
+fn frequency_to_x(rect: Rect, frequency: f32) -> f32 {
+    let position = (frequency.max(MIN_FREQUENCY) / MIN_FREQUENCY).ln() / (MAX_FREQUENCY / MIN_FREQUENCY).ln();
+    rect.left() + position.clamp(0.0, 1.0) * rect.width()
+}
+
+fn x_to_frequency(rect: Rect, x: f32) -> f32 {
+    let position = ((x - rect.left()) / rect.width()).clamp(0.0, 1.0);
+    MIN_FREQUENCY * (MAX_FREQUENCY / MIN_FREQUENCY).powf(position)
+}
+
+fn db_to_y(rect: Rect, db: f32) -> f32 {
+    let position = (db / DISPLAY_RANGE_DB).clamp(-1.0, 1.0);
+    rect.center().y - position * rect.height() * 0.5
+}
+
+fn y_to_db(rect: Rect, y: f32) -> f32 {
+    ((rect.center().y - y) / (rect.height() * 0.5)).clamp(-1.0, 1.0) * DISPLAY_RANGE_DB
+}